@@ -1,62 +1,427 @@
-use std::{path::PathBuf, sync::Arc, time::Duration};
+use std::{
+    io::{self, IsTerminal},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
 
 use clap::Parser;
+use omvarlden_spider::{
+    budget::CrawlBudget,
+    metrics::Metrics,
+    shutdown::{Shutdown, ShutdownReason},
+};
 use webcrawler::{crawler, CrawlerOptions};
 
-use crate::options::Args;
+use crate::options::{Args, Command};
+use crate::progress::Progress;
 
 mod options;
+mod progress;
+
+/// The exit code when the crawl was stopped by a signal, after saving its
+/// state. Running the crawler again resumes the crawl.
+const EXIT_INTERRUPTED: i32 = 75;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let args = Args::parse();
 
+    let mut progress = (args.command.is_none() && !args.no_progress && io::stderr().is_terminal())
+        .then(Progress::new);
+    init_tracing(&args, progress.as_ref())?;
+    if let Some(command) = args.command {
+        return run_command(command).await;
+    }
+
     let delay_ms = args.delay_ms;
     let crawling_concurrency = args.crawling_concurrency;
     let processing_concurrency = args.processing_concurrency;
     let state_path = args.state;
-    let output = args.output;
-
-    init_tracing()?;
+    let output = args.output.unwrap_or_else(|| PathBuf::from("./output"));
+    let storage = args.storage;
+    let mut seeds = args.seeds.clone();
+    if let Some(seeds_file) = &args.seeds_file {
+        seeds.extend(omvarlden_spider::omvarlden::read_seeds(seeds_file)?);
+    }
+    // only fetch the given urls, not the whole site
+    let fetch_only = args.retry_failed || args.only_seeds;
+    let start_urls = if args.retry_failed {
+        let failures = omvarlden_spider::failures::pending_failures(&output)?;
+        let urls: Vec<String> = failures
+            .iter()
+            .filter(|failure| failure["error"]["retryable"].as_bool().unwrap_or(true))
+            .filter_map(|failure| failure["url"].as_str().map(str::to_string))
+            .collect();
+        if urls.len() < failures.len() {
+            tracing::info!(
+                "skipping {} urls that failed with errors that are not retryable",
+                failures.len() - urls.len()
+            );
+        }
+        if urls.is_empty() {
+            println!("no failed urls to retry in '{}'", output.display());
+            return Ok(());
+        }
+        tracing::info!("retrying {} failed urls", urls.len());
+        Some(urls)
+    } else if args.only_seeds {
+        if seeds.is_empty() {
+            anyhow::bail!("--only-seeds needs --seed or --seeds-file");
+        }
+        tracing::info!("fetching {} seed urls", seeds.len());
+        Some(std::mem::take(&mut seeds))
+    } else if args.replace_start_urls {
+        if seeds.is_empty() {
+            anyhow::bail!("--replace-start-urls needs --seed or --seeds-file");
+        }
+        tracing::info!("crawling from {} seed urls", seeds.len());
+        Some(std::mem::take(&mut seeds))
+    } else {
+        None
+    };
 
     let spider = Arc::new(omvarlden_spider::omvarlden::OmvarldenSpider::new(
         omvarlden_spider::omvarlden::OmvarldenSpiderOptions {
             user_agent: Some(APP_USER_AGENT.into()),
-            output_path: output.unwrap_or_else(|| PathBuf::from("./output")),
+            output_path: output.clone(),
+            storage: storage.into(),
+            start_urls,
+            seeds,
+            follow_links: !fetch_only,
+            budget: CrawlBudget {
+                max_pages: args.max_pages,
+                max_depth: args.max_depth,
+                max_bytes: args.max_bytes,
+                max_audio_bytes: args.max_audio_bytes,
+                max_duration: args.max_duration,
+            },
+            frontier_batch: crawling_concurrency,
+            dry_run: args.dry_run,
+            ..Default::default()
         },
     )?);
 
+    if let Some(addr) = args.metrics_addr {
+        spider.metrics().serve(addr)?;
+    }
+    if let Some(textfile) = &args.metrics_textfile {
+        spider.metrics().write_textfile_every(
+            textfile.clone(),
+            Duration::from_secs(args.metrics_interval_secs),
+        );
+    }
+
+    if let Some(progress) = &mut progress {
+        progress.start(spider.metrics());
+    }
+
+    let shutdown = spider.shutdown();
+    tokio::spawn(shutdown_on_signal(shutdown.clone()));
+    if let Some(max_duration) = args.max_duration {
+        tokio::spawn(shutdown_after(shutdown.clone(), max_duration));
+    }
+
     crawler::run_with_options(
-        spider,
-        tokio::signal::ctrl_c(),
+        spider.clone(),
+        {
+            let shutdown = shutdown.clone();
+            async move { shutdown.wait().await }
+        },
         CrawlerOptions {
-            saved_state_path: Some(state_path),
+            // the failed urls and seeds may be visited in the saved state, and
+            // a dry run doesn't write it
+            saved_state_path: (!fetch_only && !args.dry_run).then_some(state_path),
             delay: Duration::from_millis(delay_ms),
             crawling_concurrency,
             processing_concurrency,
         },
     )
     .await;
+    if shutdown.is_triggered() {
+        drain(
+            &spider.metrics(),
+            Duration::from_secs(args.shutdown_timeout_secs),
+        )
+        .await;
+    }
+    if let Some(progress) = progress {
+        progress.finish();
+    }
+    spider.finish()?;
+    if let Some(textfile) = &args.metrics_textfile {
+        spider.metrics().write_textfile(textfile)?;
+    }
+
+    let summary = spider.summary();
+    print!("{}", summary);
+    let dry_run_report = spider.dry_run_report();
+    if let Some(report) = &dry_run_report {
+        print!("{}", report);
+    } else {
+        let stats_path = output.join(omvarlden_spider::stats::STATS_FILE);
+        summary.write(&stats_path)?;
+        println!("wrote statistics to '{}'", stats_path.display());
+        let failed = omvarlden_spider::failures::pending_failures(&output)?.len();
+        if failed > 0 {
+            println!(
+                "{} urls failed, fetch them again with --retry-failed",
+                failed
+            );
+        }
+    }
 
+    if let Some(reason) = shutdown.reason() {
+        println!("stopped early: {}", reason);
+        match reason {
+            ShutdownReason::Signal(_) => std::process::exit(EXIT_INTERRUPTED),
+            // running out of budget is how a bounded crawl ends
+            ShutdownReason::Budget(_) => {}
+        }
+    }
+    if let Some(report) = dry_run_report.filter(|report| !report.is_ok()) {
+        anyhow::bail!("found {} collisions", report.collisions.len());
+    }
     Ok(())
 }
 
-/// construct a subscriber that prints formatted traces to stdout
-fn init_tracing() -> anyhow::Result<()> {
-    use std::io;
-    use tracing_subscriber::EnvFilter;
-
-    let subscriber = tracing_subscriber::fmt()
-        .json()
-        // .with_span_events(FmtSpan::NEW | FmtSpan::EXIT)
-        .with_env_filter(
-            EnvFilter::try_from_default_env()
-                .or_else(|_| EnvFilter::try_new("fetch_sfs=info,info"))
-                .expect("telemetry: Creating EnvFilter"),
-        )
-        .with_writer(io::stderr)
-        .finish();
-    tracing::subscriber::set_global_default(subscriber)?;
+/// Trigger `shutdown` on SIGINT (ctrl-c), SIGTERM or SIGHUP.
+async fn shutdown_on_signal(shutdown: Shutdown) {
+    #[cfg(unix)]
+    let signal = {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let (mut terminate, mut hangup) = match (
+            signal(SignalKind::terminate()),
+            signal(SignalKind::hangup()),
+        ) {
+            (Ok(terminate), Ok(hangup)) => (terminate, hangup),
+            (Err(error), _) | (_, Err(error)) => {
+                tracing::error!("can't handle SIGTERM and SIGHUP: {}", error);
+                return;
+            }
+        };
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => "SIGINT",
+            _ = terminate.recv() => "SIGTERM",
+            _ = hangup.recv() => "SIGHUP",
+        }
+    };
+    #[cfg(not(unix))]
+    let signal = {
+        let _ = tokio::signal::ctrl_c().await;
+        "ctrl-c"
+    };
+    shutdown.trigger(ShutdownReason::Signal(signal));
+}
+
+/// Trigger `shutdown` when the crawl has run for `max_duration`, whether or
+/// not fetches complete.
+async fn shutdown_after(shutdown: Shutdown, max_duration: Duration) {
+    tokio::time::sleep(max_duration).await;
+    shutdown.trigger(ShutdownReason::Budget("duration"));
+}
+
+/// Wait for the items being processed, at most `timeout`.
+async fn drain(metrics: &Metrics, timeout: Duration) {
+    let drained = tokio::time::timeout(timeout, async {
+        while metrics.snapshot().items_in_flight > 0 {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await;
+    if drained.is_err() {
+        tracing::warn!(
+            "gave up waiting for {} items being processed",
+            metrics.snapshot().items_in_flight
+        );
+    }
+}
+
+async fn run_command(command: Command) -> anyhow::Result<()> {
+    match command {
+        Command::Link { output } => {
+            let report = omvarlden_spider::linking::link_episodes(&output)?;
+            println!(
+                "found {} links between {} episodes and {} articles",
+                report.links, report.episodes, report.articles
+            );
+            if report.unreadable > 0 {
+                println!("skipped {} unreadable files", report.unreadable);
+            }
+        }
+        Command::Manifest {
+            output,
+            jsonl,
+            csv,
+            license,
+        } => {
+            use omvarlden_spider::episodes;
+
+            let manifest = episodes::collect_episodes(&output, &license)?;
+            let rows = manifest.rows;
+            let (default_jsonl, default_csv) = episodes::default_paths(&output);
+            let jsonl = jsonl.unwrap_or(default_jsonl);
+            let csv = csv.unwrap_or(default_csv);
+            episodes::write_jsonl(&jsonl, &rows)?;
+            episodes::write_csv(&csv, &rows)?;
+            let complete = rows
+                .iter()
+                .filter(|row| row.status == episodes::EpisodeStatus::Complete)
+                .count();
+            println!(
+                "wrote {} episodes ({} complete) to '{}' and '{}'",
+                rows.len(),
+                complete,
+                jsonl.display(),
+                csv.display()
+            );
+            if !manifest.unreadable.is_empty() {
+                println!(
+                    "skipped {} unreadable episode metadata files:",
+                    manifest.unreadable.len()
+                );
+                for path in &manifest.unreadable {
+                    println!("  {}", path.display());
+                }
+            }
+        }
+        Command::Stats { output, json } => {
+            let summary = omvarlden_spider::stats::summarize_output(&output)?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&summary.to_json())?);
+            } else {
+                print!("{}", summary);
+            }
+        }
+        Command::Inspect {
+            target,
+            url,
+            output,
+            storage,
+            json,
+        } => {
+            use omvarlden_spider::inspect::{self, InspectOptions};
+
+            let options = InspectOptions {
+                user_agent: Some(APP_USER_AGENT.into()),
+                output_path: output,
+                storage: storage.into(),
+            };
+            let inspection = if target.starts_with("http://") || target.starts_with("https://") {
+                inspect::inspect_url(&target, &options).await?
+            } else {
+                inspect::inspect_file(target.as_ref(), &url, &options)?
+            };
+            if json {
+                println!("{}", serde_json::to_string_pretty(&inspection.to_json())?);
+            } else {
+                print!("{}", inspection);
+            }
+        }
+        Command::Verify { output, requeue } => {
+            use omvarlden_spider::verify::{self, ProblemKind};
+
+            let report = verify::verify(&output)?;
+            for problem in &report.problems {
+                println!(
+                    "{:<8} {} ({})",
+                    problem.kind.as_str(),
+                    problem.path.display(),
+                    problem.reason
+                );
+            }
+            println!(
+                "checked {} files: {} missing, {} corrupt, {} orphaned",
+                report.files_checked,
+                report.count(ProblemKind::Missing),
+                report.count(ProblemKind::Corrupt),
+                report.count(ProblemKind::Orphaned)
+            );
+            if let Some(requeue) = requeue {
+                let urls = report.requeue_urls();
+                verify::write_requeue(&requeue, &urls)?;
+                println!("wrote {} urls to '{}'", urls.len(), requeue.display());
+            }
+            if !report.is_ok() {
+                anyhow::bail!("found {} problems", report.problems.len());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Log to stderr, in a tree with `--trace`, and as JSON to `--log-file`.
+///
+/// The level is `info` for this crawler, `debug` with `--verbose` and `warn`
+/// with `--no-verbose`, unless set by `RUST_LOG`. While `progress` is shown,
+/// stderr only gets warnings unless `--verbose` is given.
+fn init_tracing(args: &Args, progress: Option<&Progress>) -> anyhow::Result<()> {
+    use std::{fs, sync::Mutex};
+    use tracing_subscriber::{
+        fmt::{self, writer::BoxMakeWriter},
+        layer::SubscriberExt,
+        util::SubscriberInitExt,
+        EnvFilter, Layer,
+    };
+
+    let level = if args.verbose {
+        "debug"
+    } else if args.no_verbose {
+        "warn"
+    } else {
+        "info"
+    };
+    let filter = |level: &str| {
+        EnvFilter::try_from_default_env().or_else(|_| {
+            EnvFilter::try_new(format!(
+                "warn,omvarlden_crawler={0},omvarlden_spider={0},webcrawler={0}",
+                level
+            ))
+        })
+    };
+    let console_level = if progress.is_some() && !args.verbose {
+        "warn"
+    } else {
+        level
+    };
+    let writer = || match progress {
+        Some(progress) => {
+            let writer = progress.log_writer();
+            BoxMakeWriter::new(move || writer.clone())
+        }
+        None => BoxMakeWriter::new(io::stderr),
+    };
+    let ansi = io::stderr().is_terminal();
+    let console = if args.trace {
+        tracing_tree::HierarchicalLayer::new(2)
+            .with_writer(writer())
+            .with_targets(true)
+            .with_bracketed_fields(true)
+            .with_ansi(ansi)
+            .boxed()
+    } else {
+        fmt::layer().with_writer(writer()).with_ansi(ansi).boxed()
+    };
+    let log_file = match &args.log_file {
+        Some(path) => {
+            let file = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)?;
+            Some(
+                fmt::layer()
+                    .json()
+                    .with_writer(Mutex::new(file))
+                    .with_filter(filter(level)?),
+            )
+        }
+        None => None,
+    };
+    tracing_subscriber::registry()
+        .with(console.with_filter(filter(console_level)?))
+        .with(log_file)
+        .try_init()?;
     Ok(())
 }
 
@@ -1,7 +1,7 @@
-use std::path::PathBuf;
+use std::{net::SocketAddr, path::PathBuf, time::Duration};
 
 #[derive(Debug, clap::Parser)]
-#[clap(name = "fetch-sfs",author,version,about,long_about=None)]
+#[clap(name = "omvarlden-crawler",author,version,about,long_about=None)]
 pub struct Args {
     /// Display verbose messages and progress information
     #[clap(long, short = 'v')]
@@ -15,6 +15,14 @@ pub struct Args {
     #[clap(long, conflicts_with("verbose"))]
     pub no_verbose: bool,
 
+    /// Don't show crawl progress, which is shown when stderr is a terminal
+    #[clap(long)]
+    pub no_progress: bool,
+
+    /// Also write logs as JSON lines to this file
+    #[clap(long)]
+    pub log_file: Option<PathBuf>,
+
     /// The delay (in milliseconds) to wait betweeen downloads.
     #[clap(long, default_value = "500")]
     pub delay_ms: u64,
@@ -31,6 +39,224 @@ pub struct Args {
     #[clap(long, short = 's', default_value = "visited.json")]
     pub state: PathBuf,
 
+    /// Only fetch the URLs in OUTPUT/crawl_failures.jsonl that still fail with
+    /// retryable errors, without following links or using the saved state
+    #[clap(long)]
+    pub retry_failed: bool,
+
+    /// Also start the crawl from this URL, can be given more than once
+    #[clap(long = "seed", value_name = "URL", value_parser = parse_url)]
+    pub seeds: Vec<String>,
+
+    /// Also start the crawl from the URLs in this file, one per line, e.g. a
+    /// requeue file written by `verify`
+    #[clap(long)]
+    pub seeds_file: Option<PathBuf>,
+
+    /// Only fetch the seeds, instead of crawling the site from them, without
+    /// following links or using the saved state
+    #[clap(long, conflicts_with("retry_failed"))]
+    pub only_seeds: bool,
+
+    /// Start the crawl from the seeds instead of the front pages and archive,
+    /// still following links and using the saved state
+    #[clap(long, conflicts_with_all(["retry_failed", "only_seeds"]))]
+    pub replace_start_urls: bool,
+
+    /// How long (in seconds) to wait for items being processed when shutting down
+    #[clap(long, default_value = "30")]
+    pub shutdown_timeout_secs: u64,
+
+    /// Stop after fetching this many URLs
+    #[clap(long)]
+    pub max_pages: Option<u64>,
+
+    /// Don't follow links more than this many steps from the start URLs, URLs
+    /// left in the saved state by an earlier crawl count as start URLs
+    #[clap(long)]
+    pub max_depth: Option<u32>,
+
+    /// Stop after downloading this much, e.g. 500M or 2G
+    #[clap(long, value_parser = parse_bytes)]
+    pub max_bytes: Option<u64>,
+
+    /// Stop after downloading this much podcast audio, e.g. 10G
+    #[clap(long, value_parser = parse_bytes)]
+    pub max_audio_bytes: Option<u64>,
+
+    /// Stop after running this long, e.g. 90s, 30m or 8h
+    #[clap(long, value_parser = parse_duration)]
+    pub max_duration: Option<Duration>,
+
+    /// Crawl without writing anything, only show where pages and episodes
+    /// would be stored and which would collide or overwrite existing files
+    #[clap(long)]
+    pub dry_run: bool,
+
+    /// How to store downloaded artefacts
+    #[clap(long, value_enum, default_value_t = Storage::Tree)]
+    pub storage: Storage,
+
+    /// Serve Prometheus metrics at http://ADDR/metrics, e.g. 127.0.0.1:9898
+    #[clap(long)]
+    pub metrics_addr: Option<SocketAddr>,
+
+    /// Write Prometheus metrics to this file, for the node_exporter textfile collector
+    #[clap(long)]
+    pub metrics_textfile: Option<PathBuf>,
+
+    /// How often (in seconds) to write the metrics textfile
+    #[clap(long, default_value = "15")]
+    pub metrics_interval_secs: u64,
+
     /// Path to save downloaded artefacts
     pub output: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Storage {
+    /// One file per page or episode, mirroring the URL paths
+    Tree,
+    /// Like `tree`, but identical content is stored once, linked from a blob store
+    ContentAddressed,
+    /// A single SQLite database, OUTPUT/omvarlden.sqlite
+    #[cfg(feature = "sqlite")]
+    Sqlite,
+    /// A single tar archive, OUTPUT/omvarlden.tar, appended to by every crawl
+    Tar,
+    /// A zip archive per crawl, OUTPUT/omvarlden-<start time>.zip
+    Zip,
+}
+
+impl From<Storage> for omvarlden_spider::storage::StorageKind {
+    fn from(storage: Storage) -> Self {
+        match storage {
+            Storage::Tree => Self::Tree,
+            Storage::ContentAddressed => Self::ContentAddressed,
+            #[cfg(feature = "sqlite")]
+            Storage::Sqlite => Self::Sqlite,
+            Storage::Tar => Self::Tar,
+            Storage::Zip => Self::Zip,
+        }
+    }
+}
+
+#[derive(Debug, clap::Subcommand)]
+pub enum Command {
+    /// Link podcast episodes to their articles in an existing output directory
+    Link {
+        /// Path to the downloaded artefacts
+        #[clap(default_value = "./output")]
+        output: PathBuf,
+    },
+    /// Write a manifest (JSONL and CSV) of the downloaded podcast episodes
+    Manifest {
+        /// Path to the downloaded artefacts
+        #[clap(default_value = "./output")]
+        output: PathBuf,
+
+        /// Where to write the JSONL manifest [default: OUTPUT/episodes.jsonl]
+        #[clap(long)]
+        jsonl: Option<PathBuf>,
+
+        /// Where to write the CSV manifest [default: OUTPUT/episodes.csv]
+        #[clap(long)]
+        csv: Option<PathBuf>,
+
+        /// License notes to record for every episode
+        #[clap(long, default_value = omvarlden_spider::episodes::DEFAULT_LICENSE)]
+        license: String,
+    },
+    /// Summarize a crawl from the crawl manifest of an existing output directory
+    Stats {
+        /// Path to the downloaded artefacts
+        #[clap(default_value = "./output")]
+        output: PathBuf,
+
+        /// Print the summary as JSON
+        #[clap(long)]
+        json: bool,
+    },
+    /// Run the extraction of the crawl on one URL or local file and show the
+    /// links, items, article fields and output paths, without storing anything
+    Inspect {
+        /// A URL to fetch, or a file with a page, e.g. an `.html.gz` from the output
+        target: String,
+
+        /// The URL a file was fetched from
+        #[clap(long, default_value = "https://www.omvarlden.se/")]
+        url: String,
+
+        /// The output directory to show paths in
+        #[clap(long, default_value = "./output")]
+        output: PathBuf,
+
+        /// The storage to show paths for
+        #[clap(long, value_enum, default_value_t = Storage::Tree)]
+        storage: Storage,
+
+        /// Print the inspection as JSON
+        #[clap(long)]
+        json: bool,
+    },
+    /// Check that the files in an existing output directory are complete and
+    /// agree with the crawl manifest
+    Verify {
+        /// Path to the downloaded artefacts
+        #[clap(default_value = "./output")]
+        output: PathBuf,
+
+        /// Write the URLs of missing and corrupt files to this file, one per line
+        #[clap(long)]
+        requeue: Option<PathBuf>,
+    },
+}
+
+fn parse_url(value: &str) -> Result<String, String> {
+    omvarlden_spider::omvarlden::parse_seed(value).map_err(|error| error.to_string())
+}
+
+/// Parse a number of bytes with an optional binary suffix: `K`, `M`, `G` or `T`.
+fn parse_bytes(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let number = value.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let factor: u64 = match value[number.len()..].to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        "T" | "TB" | "TIB" => 1 << 40,
+        suffix => return Err(format!("unknown unit '{}'", suffix)),
+    };
+    let number: u64 = number
+        .trim()
+        .parse()
+        .map_err(|error| format!("invalid number '{}': {}", number, error))?;
+    number
+        .checked_mul(factor)
+        .ok_or_else(|| format!("'{}' is too large", value))
+}
+
+/// Parse a duration with an optional suffix: `s` (the default), `m`, `h` or `d`.
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let number = value.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let factor = match &value[number.len()..] {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        suffix => return Err(format!("unknown unit '{}'", suffix)),
+    };
+    let number: u64 = number
+        .trim()
+        .parse()
+        .map_err(|error| format!("invalid number '{}': {}", number, error))?;
+    number
+        .checked_mul(factor)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("'{}' is too large", value))
 }
@@ -0,0 +1,191 @@
+//! Live progress of a crawl, drawn on stderr for interactive runs.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io::{self, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use indicatif::{HumanBytes, MultiProgress, ProgressBar, ProgressStyle};
+use omvarlden_spider::metrics::{Metrics, MetricsSnapshot};
+
+/// How often the progress is updated.
+const TICK: Duration = Duration::from_secs(1);
+/// Download rates are averaged over this many ticks.
+const RATE_WINDOW: usize = 10;
+
+pub struct Progress {
+    multi: MultiProgress,
+    bars: Bars,
+    stop: Arc<AtomicBool>,
+    updater: Option<thread::JoinHandle<()>>,
+}
+
+#[derive(Clone)]
+struct Bars {
+    urls: ProgressBar,
+    rates: ProgressBar,
+    episodes: ProgressBar,
+}
+
+impl Progress {
+    pub fn new() -> Self {
+        let multi = MultiProgress::new();
+        let urls = multi.add(ProgressBar::new_spinner().with_style(
+            ProgressStyle::with_template("{spinner} {elapsed_precise} {wide_msg}").unwrap(),
+        ));
+        urls.enable_steady_tick(Duration::from_millis(200));
+        let rates = multi.add(
+            ProgressBar::new_spinner()
+                .with_style(ProgressStyle::with_template("  {wide_msg}").unwrap()),
+        );
+        let episodes = multi.add(
+            ProgressBar::new(0).with_style(
+                ProgressStyle::with_template("  episodes [{bar:30}] {pos}/{len}, ETA {eta}")
+                    .unwrap()
+                    .progress_chars("=> "),
+            ),
+        );
+        Self {
+            multi,
+            bars: Bars {
+                urls,
+                rates,
+                episodes,
+            },
+            stop: Arc::new(AtomicBool::new(false)),
+            updater: None,
+        }
+    }
+
+    /// A writer for logs, printing them above the progress bars.
+    pub fn log_writer(&self) -> LogWriter {
+        LogWriter(self.multi.clone())
+    }
+
+    /// Show the progress in `metrics` until [`Progress::finish`] is called.
+    pub fn start(&mut self, metrics: Arc<Metrics>) {
+        let bars = self.bars.clone();
+        let stop = self.stop.clone();
+        self.updater = Some(thread::spawn(move || {
+            let mut history = VecDeque::new();
+            while !stop.load(Ordering::Relaxed) {
+                bars.update(&metrics.snapshot(), &mut history);
+                thread::park_timeout(TICK);
+            }
+        }));
+    }
+
+    /// Stop updating and remove the progress bars.
+    pub fn finish(mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(updater) = self.updater.take() {
+            updater.thread().unpark();
+            let _ = updater.join();
+        }
+        for bar in [&self.bars.urls, &self.bars.rates, &self.bars.episodes] {
+            bar.finish_and_clear();
+        }
+        let _ = self.multi.clear();
+    }
+}
+
+impl Bars {
+    /// Show `snapshot`, with download rates computed from the earlier
+    /// snapshots' bytes per host in `history`.
+    fn update(
+        &self,
+        snapshot: &MetricsSnapshot,
+        history: &mut VecDeque<(Instant, BTreeMap<String, u64>)>,
+    ) {
+        let items = snapshot
+            .items
+            .iter()
+            .map(|(kind, count)| format!("{} {}", kind, count))
+            .collect::<Vec<_>>();
+        let mut errors = snapshot.error_count().to_string();
+        if !snapshot.errors.is_empty() {
+            let codes = snapshot
+                .errors
+                .iter()
+                .map(|(code, count)| format!("{} {}", code, count))
+                .collect::<Vec<_>>();
+            errors.push_str(&format!(" ({})", codes.join(", ")));
+        }
+        self.urls.set_message(format!(
+            "urls: {} known, {} visited, {} queued, {} fetching | items: {} | errors: {}",
+            snapshot.known_urls(),
+            snapshot.visited_urls(),
+            snapshot.queued_urls(),
+            snapshot.fetches_in_flight,
+            if items.is_empty() {
+                "none".to_string()
+            } else {
+                items.join(", ")
+            },
+            errors
+        ));
+
+        let now = Instant::now();
+        let bytes: BTreeMap<String, u64> = snapshot
+            .hosts
+            .iter()
+            .map(|(host, metrics)| (host.clone(), metrics.bytes))
+            .collect();
+        let rates = match history.front() {
+            Some((then, earlier)) => {
+                let secs = now.duration_since(*then).as_secs_f64().max(f64::EPSILON);
+                bytes
+                    .iter()
+                    .map(|(host, bytes)| {
+                        let earlier = earlier.get(host).copied().unwrap_or_default();
+                        let rate = bytes.saturating_sub(earlier) as f64 / secs;
+                        format!("{} {}/s", host, HumanBytes(rate as u64))
+                    })
+                    .collect::<Vec<_>>()
+            }
+            None => Vec::new(),
+        };
+        self.rates.set_message(format!(
+            "download: {}",
+            if rates.is_empty() {
+                "-".to_string()
+            } else {
+                rates.join(", ")
+            }
+        ));
+        history.push_back((now, bytes));
+        if history.len() > RATE_WINDOW {
+            history.pop_front();
+        }
+
+        // every episode listed on the podcast's pages is downloaded once
+        let downloaded = snapshot.items("podd");
+        self.episodes
+            .set_length(snapshot.items("podd_meta").max(downloaded));
+        self.episodes.set_position(downloaded);
+    }
+}
+
+/// Writes logs to stderr above the progress bars.
+#[derive(Clone)]
+pub struct LogWriter(MultiProgress);
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.suspend(|| io::stderr().write(buf))
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.0.suspend(|| io::stderr().write_all(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
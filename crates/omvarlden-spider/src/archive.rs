@@ -0,0 +1,522 @@
+//! Following the archives of the sections.
+//!
+//! Section index pages, like `/nyheter/`, only list the latest articles of
+//! their section. Older articles are reached through "next" and page links, or
+//! a "load more" button fetching more articles, as HTML or JSON, from the URL
+//! in its `data-*` attributes. An [`Archive`] finds these links and follows
+//! them page by page, until a page links to nothing not seen on the earlier
+//! pages of the archive, that is past the oldest article.
+//!
+//! The later pages can't always be told from other URLs by their URL, so the
+//! pages found are appended to `crawl_archive.txt` in the output directory, one
+//! `<section> <url>` per line, and a resumed crawl recognizes the pages queued
+//! by an earlier one.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use reqwest::Url;
+use scraper::{Html, Selector};
+
+use crate::files::read_lines;
+use crate::omvarlden::OmvarldenSpider;
+use crate::Error;
+
+pub const ARCHIVE_FILE: &str = "crawl_archive.txt";
+
+/// The sections, by the last path segment of their index page.
+pub const SECTIONS: [&str; 7] = [
+    "nyheter",
+    "globala-malen",
+    "opinion",
+    "reportage",
+    "intervjuer",
+    "poddar",
+    "teman",
+];
+
+/// Keys in JSON "load more" responses holding the URL or number of the next
+/// page.
+const NEXT_KEYS: [&str; 6] = [
+    "next",
+    "next_url",
+    "nextUrl",
+    "next_page",
+    "nextPage",
+    "nextPageUrl",
+];
+
+/// Keys in JSON "load more" responses holding the URL of an article.
+const LINK_KEYS: [&str; 4] = ["url", "href", "link", "permalink"];
+
+/// Attributes of "load more" buttons holding the URL of the next page.
+const MORE_ATTRIBUTES: [&str; 5] = [
+    "data-url",
+    "data-href",
+    "data-next",
+    "data-next-url",
+    "data-endpoint",
+];
+
+/// The query parameter numbering the pages of an archive.
+const PAGE_PARAMETER: &str = "page";
+
+/// The section whose index page `url` is, if any.
+pub fn index_section(url: &Url) -> Option<&'static str> {
+    let last_segment = url
+        .path_segments()
+        .and_then(|mut segments| segments.rfind(|segment| !segment.is_empty()))?;
+    SECTIONS
+        .iter()
+        .find(|section| **section == last_segment)
+        .copied()
+}
+
+/// The links on a page of an archive.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ArchivePage {
+    /// Articles and other pages linked to.
+    pub links: Vec<String>,
+    /// Later pages of the archive.
+    pub next: Vec<String>,
+}
+
+impl ArchivePage {
+    pub fn from_html(page_url: &Url, html: &str) -> Self {
+        let mut page = Self::default();
+        page.add_html(page_url, &Html::parse_document(html));
+        page
+    }
+
+    /// The links in a JSON "load more" response, in fields like `url` or in
+    /// HTML fragments, and the next page given by fields like `next`.
+    pub fn from_json(page_url: &Url, json: &serde_json::Value) -> Self {
+        let mut page = Self::default();
+        page.add_json(page_url, None, json);
+        page
+    }
+
+    fn add_html(&mut self, page_url: &Url, document: &Html) {
+        let next_selector = Selector::parse(
+            r#"link[rel~="next"][href], a[rel~="next"][href], [class*="pagination"] a[href],
+            a[class*="next"][href], a[class*="load-more"][href], a[class*="loadmore"][href]"#,
+        )
+        .unwrap();
+        for link in document.select(&next_selector) {
+            self.next
+                .extend(link.attr("href").and_then(|href| resolve(page_url, href)));
+        }
+        let more_selector =
+            Selector::parse(r#"[class*="more"], [class*="next"], [id*="more"]"#).unwrap();
+        for button in document.select(&more_selector) {
+            for attribute in MORE_ATTRIBUTES {
+                self.next.extend(
+                    button
+                        .attr(attribute)
+                        .and_then(|href| resolve(page_url, href)),
+                );
+            }
+        }
+        let link_selector = Selector::parse("a[href]").unwrap();
+        for link in document.select(&link_selector) {
+            self.links
+                .extend(link.attr("href").and_then(|href| resolve(page_url, href)));
+        }
+        // pagination links aren't articles
+        self.links.retain(|link| !self.next.contains(link));
+    }
+
+    fn add_json(&mut self, page_url: &Url, key: Option<&str>, value: &serde_json::Value) {
+        let is_next = key.is_some_and(|key| NEXT_KEYS.contains(&key));
+        match value {
+            serde_json::Value::Object(fields) => {
+                for (key, value) in fields {
+                    self.add_json(page_url, Some(key), value);
+                }
+            }
+            serde_json::Value::Array(values) => {
+                for value in values {
+                    self.add_json(page_url, key, value);
+                }
+            }
+            serde_json::Value::Number(number) if is_next => {
+                if let Some(page) = number.as_u64() {
+                    self.next.push(with_page(page_url, page));
+                }
+            }
+            serde_json::Value::String(href) if is_next => {
+                self.next.extend(resolve(page_url, href));
+            }
+            serde_json::Value::String(html) if html.contains('<') => {
+                self.add_html(page_url, &Html::parse_fragment(html));
+            }
+            serde_json::Value::String(href) if key.is_some_and(|key| LINK_KEYS.contains(&key)) => {
+                self.links.extend(resolve(page_url, href));
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The pages of the section archives seen so far.
+#[derive(Debug, Default)]
+pub struct Archive {
+    /// `output_path/crawl_archive.txt`, `None` if the pages aren't saved.
+    path: Option<PathBuf>,
+    inner: Mutex<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// The section of every archive page found, besides the index pages, by
+    /// this or an earlier crawl.
+    pages: HashMap<String, &'static str>,
+    /// Open for appending the pages found, `None` if they aren't saved.
+    log: Option<fs::File>,
+    /// Links seen on the pages of each section's archive.
+    seen: HashMap<&'static str, HashSet<String>>,
+    /// Pages crawled of each section's archive.
+    page_counts: HashMap<&'static str, u32>,
+}
+
+impl Archive {
+    /// An archive that doesn't save its pages.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// An archive saving its pages to `output_path/crawl_archive.txt`, knowing
+    /// the pages saved by earlier crawls.
+    pub fn open(output_path: &Path) -> Result<Self, Error> {
+        let archive = Self::dry_run(output_path)?;
+        let path = output_path.join(ARCHIVE_FILE);
+        let log = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|error| Error::IoCreate {
+                path: path.clone(),
+                source: error,
+            })?;
+        archive.inner.lock().unwrap().log = Some(log);
+        Ok(Self {
+            path: Some(path),
+            ..archive
+        })
+    }
+
+    /// Like [`Archive::open`], without ever writing the file.
+    pub fn dry_run(output_path: &Path) -> Result<Self, Error> {
+        let path = output_path.join(ARCHIVE_FILE);
+        let mut pages = HashMap::new();
+        if path.exists() {
+            for line in read_lines(&path)? {
+                let section = line.split_once(' ').and_then(|(section, url)| {
+                    let section = SECTIONS.iter().find(|known| **known == section)?;
+                    Some((url.to_string(), *section))
+                });
+                match section {
+                    Some((url, section)) => {
+                        pages.insert(url, section);
+                    }
+                    None => tracing::warn!("skipping bad line in '{}': {}", path.display(), line),
+                }
+            }
+        }
+        Ok(Self {
+            path: None,
+            inner: Mutex::new(Inner {
+                pages,
+                ..Inner::default()
+            }),
+        })
+    }
+
+    /// The index pages of all sections.
+    pub fn start_urls() -> Vec<String> {
+        SECTIONS
+            .iter()
+            .map(|section| format!("{}/{}/", OmvarldenSpider::BASE_URL, section))
+            .collect()
+    }
+
+    /// The section whose archive `url` is a page of, if any.
+    pub fn section(&self, url: &Url) -> Option<&'static str> {
+        index_section(url).or_else(|| self.inner.lock().unwrap().pages.get(url.as_str()).copied())
+    }
+
+    /// The URLs to crawl from `page` of the archive of `section`: its links,
+    /// and its next pages as long as it links to something not seen on the
+    /// earlier pages.
+    pub fn follow(&self, section: &'static str, page_url: &Url, page: ArchivePage) -> Vec<String> {
+        let mut inner = self.inner.lock().unwrap();
+        let seen = inner.seen.entry(section).or_default();
+        let mut new_links = 0;
+        for link in &page.links {
+            if seen.insert(link.clone()) {
+                new_links += 1;
+            }
+        }
+        let page_count = inner.page_counts.entry(section).or_default();
+        *page_count += 1;
+        let page_count = *page_count;
+        let mut urls = page.links;
+        if new_links == 0 {
+            tracing::info!(
+                "reached the end of the {} archive after {} pages",
+                section,
+                page_count
+            );
+            return urls;
+        }
+        let mut next = page.next;
+        // pagination also links back to the earlier pages
+        let current = page_number(page_url).unwrap_or(1);
+        next.retain(|next| {
+            Url::parse(next)
+                .ok()
+                .and_then(|next| page_number(&next))
+                .map_or(true, |number| number > current)
+        });
+        if next.is_empty() {
+            // "load more" buttons requesting the next page by number from script
+            if let Some(number) = page_number(page_url) {
+                next.push(with_page(page_url, number + 1));
+            }
+        }
+        tracing::debug!(section, new_links, next = ?next, "archive page");
+        let mut lines = String::new();
+        for next in next {
+            if inner.pages.insert(next.clone(), section).is_none() {
+                lines.push_str(&format!("{} {}\n", section, next));
+            }
+            urls.push(next);
+        }
+        if let (Some(log), Some(path)) = (inner.log.as_mut(), &self.path) {
+            // one write, so concurrent runs don't interleave lines
+            if let Err(error) = log.write_all(lines.as_bytes()) {
+                tracing::warn!(
+                    "failed saving archive pages to '{}': {}",
+                    path.display(),
+                    error
+                );
+            }
+        }
+        urls
+    }
+}
+
+/// `href` resolved against `page_url`, without fragment, if it is on
+/// OmVärlden.
+fn resolve(page_url: &Url, href: &str) -> Option<String> {
+    let mut url = page_url.join(href.trim()).ok()?;
+    url.set_fragment(None);
+    let base = Url::parse(OmvarldenSpider::BASE_URL).unwrap();
+    (url.host_str() == base.host_str()).then(|| url.to_string())
+}
+
+fn page_number(url: &Url) -> Option<u64> {
+    url.query_pairs()
+        .find(|(name, _)| name == PAGE_PARAMETER)
+        .and_then(|(_, value)| value.parse().ok())
+}
+
+/// `url` with its page number set to `page`.
+fn with_page(url: &Url, page: u64) -> String {
+    let pairs: Vec<(String, String)> = url
+        .query_pairs()
+        .filter(|(name, _)| name != PAGE_PARAMETER)
+        .map(|(name, value)| (name.into_owned(), value.into_owned()))
+        .collect();
+    let mut url = url.clone();
+    url.query_pairs_mut()
+        .clear()
+        .extend_pairs(pairs)
+        .append_pair(PAGE_PARAMETER, &page.to_string());
+    url.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(path: &str) -> Url {
+        Url::parse(&format!("{}{}", OmvarldenSpider::BASE_URL, path)).unwrap()
+    }
+
+    fn link(path: &str) -> String {
+        url(path).to_string()
+    }
+
+    #[test]
+    fn index_sections() {
+        assert_eq!(index_section(&url("/nyheter/")), Some("nyheter"));
+        assert_eq!(index_section(&url("/poddar")), Some("poddar"));
+        assert_eq!(index_section(&url("/nyheter/en-artikel/")), None);
+        assert_eq!(index_section(&url("/")), None);
+    }
+
+    #[test]
+    fn html_page() {
+        let html = r##"
+            <html><head><link rel="next" href="/nyheter/?page=2"></head><body>
+            <a href="/nyheter/forsta/">Första</a>
+            <a href="/nyheter/andra/#kommentarer">Andra</a>
+            <a href="https://example.com/annan/">Elsewhere</a>
+            <nav class="pagination"><a href="/nyheter/?page=2">2</a></nav>
+            <button class="load-more" data-url="/api/nyheter?page=2"></button>
+            </body></html>
+        "##;
+        let page = ArchivePage::from_html(&url("/nyheter/"), html);
+        assert_eq!(
+            page.links,
+            vec![link("/nyheter/forsta/"), link("/nyheter/andra/")]
+        );
+        assert_eq!(
+            page.next,
+            vec![
+                link("/nyheter/?page=2"),
+                link("/nyheter/?page=2"),
+                link("/api/nyheter?page=2"),
+            ]
+        );
+    }
+
+    #[test]
+    fn json_page() {
+        let json = serde_json::json!({
+            "posts": [
+                { "title": "Första", "permalink": "/nyheter/forsta/" },
+                { "title": "Andra", "url": "https://www.omvarlden.se/nyheter/andra/" },
+                { "title": "Elsewhere", "url": "https://example.com/annan/" },
+            ],
+            "html": "<article><a href=\"/nyheter/tredje/\">Tredje</a></article>",
+            "nextPage": 3,
+        });
+        let page = ArchivePage::from_json(&url("/api/nyheter?page=2"), &json);
+        assert_eq!(
+            page.links,
+            vec![
+                link("/nyheter/tredje/"),
+                link("/nyheter/forsta/"),
+                link("/nyheter/andra/"),
+            ]
+        );
+        assert_eq!(page.next, vec![link("/api/nyheter?page=3")]);
+
+        let json = serde_json::json!({ "next_url": "/api/nyheter?page=4" });
+        let page = ArchivePage::from_json(&url("/api/nyheter?page=3"), &json);
+        assert!(page.links.is_empty());
+        assert_eq!(page.next, vec![link("/api/nyheter?page=4")]);
+    }
+
+    #[test]
+    fn follows_until_nothing_new() {
+        let archive = Archive::new();
+        let first = ArchivePage {
+            links: vec![link("/nyheter/forsta/"), link("/nyheter/andra/")],
+            next: vec![link("/nyheter/?page=2")],
+        };
+        assert_eq!(
+            archive.follow("nyheter", &url("/nyheter/"), first),
+            vec![
+                link("/nyheter/forsta/"),
+                link("/nyheter/andra/"),
+                link("/nyheter/?page=2"),
+            ]
+        );
+
+        // back to the first page and on to the third, with one new article
+        let second = ArchivePage {
+            links: vec![link("/nyheter/andra/"), link("/nyheter/tredje/")],
+            next: vec![link("/nyheter/?page=1"), link("/nyheter/?page=3")],
+        };
+        assert_eq!(
+            archive.follow("nyheter", &url("/nyheter/?page=2"), second),
+            vec![
+                link("/nyheter/andra/"),
+                link("/nyheter/tredje/"),
+                link("/nyheter/?page=3"),
+            ]
+        );
+
+        // past the oldest article, the page repeats earlier ones
+        let third = ArchivePage {
+            links: vec![link("/nyheter/tredje/")],
+            next: vec![link("/nyheter/?page=4")],
+        };
+        assert_eq!(
+            archive.follow("nyheter", &url("/nyheter/?page=3"), third),
+            vec![link("/nyheter/tredje/")]
+        );
+    }
+
+    #[test]
+    fn requests_the_next_page_by_number() {
+        let archive = Archive::new();
+        let page = ArchivePage {
+            links: vec![link("/poddar/avsnitt-1/")],
+            next: Vec::new(),
+        };
+        assert_eq!(
+            archive.follow("poddar", &url("/api/poddar?sort=new&page=2"), page),
+            vec![
+                link("/poddar/avsnitt-1/"),
+                link("/api/poddar?sort=new&page=3"),
+            ]
+        );
+        // without a page number there is nothing to request
+        let page = ArchivePage {
+            links: vec![link("/poddar/avsnitt-2/")],
+            next: Vec::new(),
+        };
+        assert_eq!(
+            archive.follow("poddar", &url("/poddar/"), page),
+            vec![link("/poddar/avsnitt-2/")]
+        );
+    }
+
+    #[test]
+    fn resumed_crawls_know_the_pages_found() {
+        let output = tempfile::tempdir().unwrap();
+        let archive = Archive::open(output.path()).unwrap();
+        let page = ArchivePage {
+            links: vec![link("/nyheter/forsta/")],
+            next: vec![link("/api/load-more?offset=20")],
+        };
+        archive.follow("nyheter", &url("/nyheter/"), page);
+        assert_eq!(
+            archive.section(&url("/api/load-more?offset=20")),
+            Some("nyheter")
+        );
+        drop(archive);
+
+        let resumed = Archive::open(output.path()).unwrap();
+        assert_eq!(
+            resumed.section(&url("/api/load-more?offset=20")),
+            Some("nyheter")
+        );
+        assert_eq!(resumed.section(&url("/api/load-more?offset=40")), None);
+        let page = ArchivePage {
+            links: vec![link("/nyheter/andra/")],
+            next: vec![link("/api/load-more?offset=40")],
+        };
+        resumed.follow("nyheter", &url("/api/load-more?offset=20"), page);
+        assert_eq!(
+            Archive::dry_run(output.path())
+                .unwrap()
+                .section(&url("/api/load-more?offset=40")),
+            Some("nyheter")
+        );
+        assert_eq!(
+            read_lines(&output.path().join(ARCHIVE_FILE)).unwrap(),
+            vec![
+                format!("nyheter {}", link("/api/load-more?offset=20")),
+                format!("nyheter {}", link("/api/load-more?offset=40")),
+            ]
+        );
+    }
+}
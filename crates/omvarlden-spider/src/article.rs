@@ -0,0 +1,116 @@
+//! Extracting article fields from OmVärlden pages.
+
+use chrono::{DateTime, Utc};
+use scraper::{ElementRef, Html, Selector};
+
+use crate::date::{self, ParsedDate};
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Article {
+    pub title: Option<String>,
+    /// The publication date as written on the page.
+    pub published: Option<String>,
+    pub published_date: Option<ParsedDate>,
+    pub section: Option<String>,
+    pub lead: Option<String>,
+    pub text: String,
+    /// Links and embedded players pointing to libsyn.
+    pub podcast_urls: Vec<String>,
+}
+
+impl Article {
+    /// The article in `html`, fetched at `fetched_at`.
+    pub fn from_html(html: &str, fetched_at: DateTime<Utc>) -> Self {
+        Self::from_document(&Html::parse_document(html), fetched_at)
+    }
+
+    pub fn from_document(document: &Html, fetched_at: DateTime<Utc>) -> Self {
+        let title = meta_content(document, r#"meta[property="og:title"]"#)
+            .or_else(|| first_text(document, "h1"));
+        let published = meta_content(document, r#"meta[property="article:published_time"]"#)
+            .or_else(|| {
+                let selector = Selector::parse("time[datetime]").unwrap();
+                document
+                    .select(&selector)
+                    .next()
+                    .and_then(|time| time.attr("datetime"))
+                    .map(|datetime| datetime.trim().to_string())
+            })
+            .or_else(|| first_text(document, "time"));
+        let section = meta_content(document, r#"meta[property="article:section"]"#);
+        let lead = meta_content(document, r#"meta[property="og:description"]"#)
+            .or_else(|| meta_content(document, r#"meta[name="description"]"#));
+
+        let paragraph_selector = Selector::parse("article p").unwrap();
+        let mut paragraphs: Vec<String> = document
+            .select(&paragraph_selector)
+            .filter_map(element_text)
+            .collect();
+        if paragraphs.is_empty() {
+            let paragraph_selector = Selector::parse("p").unwrap();
+            paragraphs = document
+                .select(&paragraph_selector)
+                .filter_map(element_text)
+                .collect();
+        }
+
+        let player_selector =
+            Selector::parse("iframe[src], a[href], audio[src], source[src]").unwrap();
+        let mut podcast_urls = Vec::new();
+        for element in document.select(&player_selector) {
+            let url = element.attr("src").or_else(|| element.attr("href"));
+            if let Some(url) = url {
+                if url.contains("libsyn.com") && !podcast_urls.iter().any(|u| u == url) {
+                    podcast_urls.push(url.to_string());
+                }
+            }
+        }
+
+        Self {
+            title,
+            published_date: published.as_deref().and_then(|published| {
+                date::parse_date(published, fetched_at)
+                    .map_err(|error| tracing::warn!("{}", error))
+                    .ok()
+            }),
+            published,
+            section,
+            lead,
+            text: paragraphs.join("\n\n"),
+            podcast_urls,
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "title": self.title,
+            "published": self.published_date.as_ref().map(ParsedDate::iso),
+            "published_raw": self.published,
+            "section": self.section,
+            "lead": self.lead,
+            "text": self.text,
+            "podcast_urls": self.podcast_urls,
+        })
+    }
+}
+
+fn meta_content(document: &Html, selector: &str) -> Option<String> {
+    let selector = Selector::parse(selector).unwrap();
+    document
+        .select(&selector)
+        .next()
+        .and_then(|meta| meta.attr("content"))
+        .map(|content| content.trim().to_string())
+        .filter(|content| !content.is_empty())
+}
+
+fn first_text(document: &Html, selector: &str) -> Option<String> {
+    let selector = Selector::parse(selector).unwrap();
+    document.select(&selector).next().and_then(element_text)
+}
+
+fn element_text(element: ElementRef) -> Option<String> {
+    let text = element.text().collect::<Vec<_>>().join(" ");
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    (!text.is_empty()).then_some(text)
+}
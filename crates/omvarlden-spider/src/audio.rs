@@ -0,0 +1,694 @@
+//! Reading ID3 tags and MPEG audio properties from downloaded episodes.
+//!
+//! Only the start of the file (ID3v2 tag and the first audio frames) and the
+//! last 128 bytes (ID3v1 tag) are read, so this is cheap even for large files.
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+use crate::Error;
+
+/// How much audio data after the ID3v2 tag to scan for the first frame.
+const FRAME_SCAN_LEN: u64 = 64 * 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioMetadata {
+    pub byte_size: u64,
+    pub duration_secs: f64,
+    pub bitrate_kbps: u32,
+    pub sample_rate: u32,
+    pub channels: u8,
+    pub mpeg_version: &'static str,
+    pub layer: u8,
+    pub vbr: bool,
+    pub id3: Option<Id3Tags>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Id3Tags {
+    pub version: String,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub date: Option<String>,
+    pub genre: Option<String>,
+    pub comment: Option<String>,
+}
+
+impl AudioMetadata {
+    pub fn from_bytes(data: &[u8]) -> Result<Self, Error> {
+        Self::from_reader(io::Cursor::new(data))
+    }
+
+    pub fn from_reader<R: Read + Seek>(mut reader: R) -> Result<Self, Error> {
+        let byte_size = reader.seek(SeekFrom::End(0)).map_err(invalid)?;
+        reader.seek(SeekFrom::Start(0)).map_err(invalid)?;
+
+        let mut header = [0u8; 10];
+        let header_len = read_up_to(&mut reader, &mut header).map_err(invalid)?;
+        let id3v2_len = if header_len == 10 {
+            id3v2_tag_len(&header)
+        } else {
+            0
+        };
+
+        reader.seek(SeekFrom::Start(0)).map_err(invalid)?;
+        let mut head = Vec::new();
+        reader
+            .by_ref()
+            .take(id3v2_len + FRAME_SCAN_LEN)
+            .read_to_end(&mut head)
+            .map_err(invalid)?;
+
+        let mut tail = [0u8; 128];
+        let id3v1 = if byte_size >= 128 + id3v2_len {
+            reader.seek(SeekFrom::End(-128)).map_err(invalid)?;
+            reader.read_exact(&mut tail).map_err(invalid)?;
+            parse_id3v1(&tail)
+        } else {
+            None
+        };
+
+        let id3v2 = if id3v2_len > 0 {
+            parse_id3v2(&head[..(id3v2_len as usize).min(head.len())])
+        } else {
+            None
+        };
+
+        let audio_start = (id3v2_len as usize).min(head.len());
+        let (offset, frame) =
+            find_first_frame(&head[audio_start..]).ok_or_else(|| Error::ParseAudio {
+                reason: "no MPEG audio frame found".into(),
+            })?;
+
+        let audio_end = if id3v1.is_some() {
+            byte_size - 128
+        } else {
+            byte_size
+        };
+        let audio_len = audio_end.saturating_sub((audio_start + offset) as u64);
+
+        let frame_data = &head[audio_start + offset..];
+        let (duration_secs, bitrate_kbps, vbr) = match vbr_frame_count(&frame, frame_data) {
+            Some((frames, vbr)) if frames > 0 => {
+                let duration =
+                    frames as f64 * frame.samples_per_frame() as f64 / frame.sample_rate as f64;
+                let bitrate = (audio_len as f64 * 8.0 / duration / 1000.0).round() as u32;
+                (duration, bitrate, vbr)
+            }
+            _ => {
+                let duration = audio_len as f64 * 8.0 / (frame.bitrate_kbps as f64 * 1000.0);
+                (duration, frame.bitrate_kbps, false)
+            }
+        };
+
+        Ok(Self {
+            byte_size,
+            duration_secs,
+            bitrate_kbps,
+            sample_rate: frame.sample_rate,
+            channels: frame.channels(),
+            mpeg_version: frame.version.as_str(),
+            layer: frame.layer,
+            vbr,
+            id3: merge_tags(id3v2, id3v1),
+        })
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "byte_size": self.byte_size,
+            "duration_secs": (self.duration_secs * 1000.0).round() / 1000.0,
+            "bitrate_kbps": self.bitrate_kbps,
+            "sample_rate": self.sample_rate,
+            "channels": self.channels,
+            "mpeg_version": self.mpeg_version,
+            "layer": self.layer,
+            "vbr": self.vbr,
+            "id3": self.id3.as_ref().map(Id3Tags::to_json),
+        })
+    }
+}
+
+impl Id3Tags {
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "version": self.version,
+            "title": self.title,
+            "artist": self.artist,
+            "album": self.album,
+            "date": self.date,
+            "genre": self.genre,
+            "comment": self.comment,
+        })
+    }
+}
+
+fn invalid(error: io::Error) -> Error {
+    Error::ParseAudio {
+        reason: error.to_string(),
+    }
+}
+
+fn read_up_to<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    Ok(filled)
+}
+
+fn syncsafe(bytes: &[u8]) -> u32 {
+    bytes
+        .iter()
+        .fold(0u32, |acc, b| (acc << 7) | u32::from(b & 0x7f))
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0u32, |acc, b| (acc << 8) | u32::from(*b))
+}
+
+/// Total length of the ID3v2 tag (header, body and footer), or 0 if absent.
+fn id3v2_tag_len(header: &[u8; 10]) -> u64 {
+    if &header[0..3] != b"ID3" {
+        return 0;
+    }
+    let has_footer = header[5] & 0x10 != 0;
+    10 + u64::from(syncsafe(&header[6..10])) + if has_footer { 10 } else { 0 }
+}
+
+fn parse_id3v2(tag: &[u8]) -> Option<Id3Tags> {
+    if tag.len() < 10 || &tag[0..3] != b"ID3" {
+        return None;
+    }
+    let major = tag[3];
+    let flags = tag[5];
+    let mut tags = Id3Tags {
+        version: format!("2.{}.{}", major, tag[4]),
+        ..Default::default()
+    };
+    let mut pos = 10;
+    if flags & 0x40 != 0 && tag.len() >= 14 {
+        pos += match major {
+            3 => 4 + be_u32(&tag[10..14]) as usize,
+            _ => syncsafe(&tag[10..14]) as usize,
+        };
+    }
+
+    let (id_len, header_len) = if major == 2 { (3, 6) } else { (4, 10) };
+    while pos + header_len <= tag.len() {
+        let id = &tag[pos..pos + id_len];
+        if id[0] == 0 {
+            // padding
+            break;
+        }
+        let size = match major {
+            2 => be_u32(&tag[pos + 3..pos + 6]) as usize,
+            3 => be_u32(&tag[pos + 4..pos + 8]) as usize,
+            _ => syncsafe(&tag[pos + 4..pos + 8]) as usize,
+        };
+        let frame_flags = if major == 2 { 0 } else { tag[pos + 9] };
+        let start = pos + header_len;
+        let end = start.saturating_add(size).min(tag.len());
+        pos = start.saturating_add(size);
+        // skip compressed or encrypted frames
+        let skipped = match major {
+            3 => frame_flags & 0xc0 != 0,
+            4 => frame_flags & 0x0c != 0,
+            _ => false,
+        };
+        if skipped || start >= end {
+            continue;
+        }
+        let body = &tag[start..end];
+        match std::str::from_utf8(id).unwrap_or_default() {
+            "TIT2" | "TT2" => tags.title = decode_text(body),
+            "TPE1" | "TP1" => tags.artist = decode_text(body),
+            "TALB" | "TAL" => tags.album = decode_text(body),
+            "TDRC" | "TYER" | "TYE" => tags.date = decode_text(body),
+            "TCON" | "TCO" => tags.genre = decode_text(body),
+            "COMM" | "COM" => tags.comment = decode_comment(body),
+            _ => {}
+        }
+    }
+    Some(tags)
+}
+
+fn parse_id3v1(tail: &[u8; 128]) -> Option<Id3Tags> {
+    if &tail[0..3] != b"TAG" {
+        return None;
+    }
+    let field = |bytes: &[u8]| {
+        let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+        let text = latin1(&bytes[..end]).trim().to_string();
+        (!text.is_empty()).then_some(text)
+    };
+    Some(Id3Tags {
+        version: "1".into(),
+        title: field(&tail[3..33]),
+        artist: field(&tail[33..63]),
+        album: field(&tail[63..93]),
+        date: field(&tail[93..97]),
+        genre: None,
+        comment: field(&tail[97..127]),
+    })
+}
+
+/// Prefer ID3v2 values, falling back to ID3v1 for missing fields.
+fn merge_tags(v2: Option<Id3Tags>, v1: Option<Id3Tags>) -> Option<Id3Tags> {
+    match (v2, v1) {
+        (Some(mut v2), Some(v1)) => {
+            v2.title = v2.title.or(v1.title);
+            v2.artist = v2.artist.or(v1.artist);
+            v2.album = v2.album.or(v1.album);
+            v2.date = v2.date.or(v1.date);
+            v2.comment = v2.comment.or(v1.comment);
+            Some(v2)
+        }
+        (v2, v1) => v2.or(v1),
+    }
+}
+
+fn latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| char::from(*b)).collect()
+}
+
+fn decode_text(body: &[u8]) -> Option<String> {
+    let (&encoding, text) = body.split_first()?;
+    let text = decode_string(encoding, text);
+    let text = text.trim_matches(char::from(0)).trim();
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+fn decode_comment(body: &[u8]) -> Option<String> {
+    // encoding, 3 byte language, null-terminated short description, text
+    if body.len() < 4 {
+        return None;
+    }
+    let encoding = body[0];
+    let rest = &body[4..];
+    let text_start = match encoding {
+        1 | 2 => rest
+            .chunks_exact(2)
+            .position(|c| c == [0, 0])
+            .map(|i| i * 2 + 2),
+        _ => rest.iter().position(|b| *b == 0).map(|i| i + 1),
+    }
+    .unwrap_or(0);
+    let mut text = vec![encoding];
+    text.extend_from_slice(&rest[text_start.min(rest.len())..]);
+    decode_text(&text)
+}
+
+fn decode_string(encoding: u8, bytes: &[u8]) -> String {
+    match encoding {
+        1 | 2 => {
+            let (big_endian, bytes) = match bytes {
+                [0xfe, 0xff, rest @ ..] => (true, rest),
+                [0xff, 0xfe, rest @ ..] => (false, rest),
+                _ => (encoding == 2, bytes),
+            };
+            let units: Vec<u16> = bytes
+                .chunks_exact(2)
+                .map(|c| {
+                    if big_endian {
+                        u16::from_be_bytes([c[0], c[1]])
+                    } else {
+                        u16::from_le_bytes([c[0], c[1]])
+                    }
+                })
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        3 => String::from_utf8_lossy(bytes).into_owned(),
+        _ => latin1(bytes),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MpegVersion {
+    V1,
+    V2,
+    V25,
+}
+
+impl MpegVersion {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::V1 => "1",
+            Self::V2 => "2",
+            Self::V25 => "2.5",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct FrameHeader {
+    version: MpegVersion,
+    layer: u8,
+    bitrate_kbps: u32,
+    sample_rate: u32,
+    padding: bool,
+    mono: bool,
+}
+
+const BITRATES_V1: [[u32; 15]; 3] = [
+    [
+        0, 32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448,
+    ],
+    [
+        0, 32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384,
+    ],
+    [
+        0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+    ],
+];
+const BITRATES_V2: [[u32; 15]; 2] = [
+    [
+        0, 32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256,
+    ],
+    [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],
+];
+
+impl FrameHeader {
+    fn parse(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < 4 || bytes[0] != 0xff || bytes[1] & 0xe0 != 0xe0 {
+            return None;
+        }
+        let version = match (bytes[1] >> 3) & 0b11 {
+            0b00 => MpegVersion::V25,
+            0b10 => MpegVersion::V2,
+            0b11 => MpegVersion::V1,
+            _ => return None,
+        };
+        let layer = match (bytes[1] >> 1) & 0b11 {
+            0b01 => 3,
+            0b10 => 2,
+            0b11 => 1,
+            _ => return None,
+        };
+        let bitrate_index = (bytes[2] >> 4) as usize;
+        let sample_rate_index = ((bytes[2] >> 2) & 0b11) as usize;
+        if bitrate_index == 0 || bitrate_index == 15 || sample_rate_index == 3 {
+            return None;
+        }
+        let bitrate_kbps = match version {
+            MpegVersion::V1 => BITRATES_V1[layer as usize - 1][bitrate_index],
+            _ if layer == 1 => BITRATES_V2[0][bitrate_index],
+            _ => BITRATES_V2[1][bitrate_index],
+        };
+        let sample_rate = [44100, 48000, 32000][sample_rate_index]
+            / match version {
+                MpegVersion::V1 => 1,
+                MpegVersion::V2 => 2,
+                MpegVersion::V25 => 4,
+            };
+        Some(Self {
+            version,
+            layer,
+            bitrate_kbps,
+            sample_rate,
+            padding: bytes[2] & 0b10 != 0,
+            mono: bytes[3] >> 6 == 0b11,
+        })
+    }
+
+    fn channels(&self) -> u8 {
+        if self.mono {
+            1
+        } else {
+            2
+        }
+    }
+
+    fn samples_per_frame(&self) -> u32 {
+        match (self.layer, self.version) {
+            (1, _) => 384,
+            (3, MpegVersion::V2 | MpegVersion::V25) => 576,
+            _ => 1152,
+        }
+    }
+
+    fn frame_len(&self) -> usize {
+        let padding = u32::from(self.padding);
+        let len = match self.layer {
+            1 => (12 * self.bitrate_kbps * 1000 / self.sample_rate + padding) * 4,
+            _ => {
+                self.samples_per_frame() / 8 * self.bitrate_kbps * 1000 / self.sample_rate + padding
+            }
+        };
+        len as usize
+    }
+
+    /// Length of the layer III side information following the header.
+    fn side_info_len(&self) -> usize {
+        match (self.version, self.mono) {
+            (MpegVersion::V1, false) => 32,
+            (MpegVersion::V1, true) => 17,
+            (_, false) => 17,
+            (_, true) => 9,
+        }
+    }
+}
+
+/// Find the first frame header that is followed by another valid header.
+fn find_first_frame(data: &[u8]) -> Option<(usize, FrameHeader)> {
+    (0..data.len().saturating_sub(4)).find_map(|offset| {
+        let frame = FrameHeader::parse(&data[offset..])?;
+        let next = offset + frame.frame_len();
+        if next + 4 > data.len() {
+            // can't confirm with a second header, accept if the data ends here
+            return (next >= data.len()).then_some((offset, frame));
+        }
+        let next_frame = FrameHeader::parse(&data[next..])?;
+        (next_frame.version == frame.version
+            && next_frame.layer == frame.layer
+            && next_frame.sample_rate == frame.sample_rate)
+            .then_some((offset, frame))
+    })
+}
+
+/// Number of frames recorded in a Xing/Info or VBRI header in the first frame,
+/// and whether the header marks the stream as variable bitrate.
+fn vbr_frame_count(frame: &FrameHeader, data: &[u8]) -> Option<(u32, bool)> {
+    let xing = 4 + frame.side_info_len();
+    if let Some(tag) = data.get(xing..xing + 12) {
+        if &tag[0..4] == b"Xing" || &tag[0..4] == b"Info" {
+            let flags = be_u32(&tag[4..8]);
+            let vbr = &tag[0..4] == b"Xing";
+            return (flags & 0x1 != 0).then(|| (be_u32(&tag[8..12]), vbr));
+        }
+    }
+    let vbri = 4 + 32;
+    match data.get(vbri..vbri + 18) {
+        Some(tag) if &tag[0..4] == b"VBRI" => Some((be_u32(&tag[14..18]), true)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// MPEG 1 layer III, 128 kbps, 44.1 kHz, stereo, without padding.
+    const HEADER: [u8; 4] = [0xff, 0xfb, 0x90, 0x00];
+    const FRAME_LEN: usize = 417;
+
+    fn frames(count: usize) -> Vec<u8> {
+        let mut data = Vec::new();
+        for _ in 0..count {
+            data.extend_from_slice(&HEADER);
+            data.resize(data.len() + FRAME_LEN - HEADER.len(), 0);
+        }
+        data
+    }
+
+    fn syncsafe_bytes(value: usize) -> [u8; 4] {
+        let value = value as u32;
+        [
+            (value >> 21) as u8 & 0x7f,
+            (value >> 14) as u8 & 0x7f,
+            (value >> 7) as u8 & 0x7f,
+            value as u8 & 0x7f,
+        ]
+    }
+
+    /// An ID3v2.3 tag holding `frames` of id and body, with the size of the
+    /// tag given by `size` if set.
+    fn id3v2(frames: &[(&str, &[u8])], size: Option<usize>) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (id, frame) in frames {
+            body.extend_from_slice(id.as_bytes());
+            body.extend_from_slice(&(frame.len() as u32).to_be_bytes());
+            body.extend_from_slice(&[0, 0]);
+            body.extend_from_slice(frame);
+        }
+        let mut tag = b"ID3\x03\x00\x00".to_vec();
+        tag.extend_from_slice(&syncsafe_bytes(size.unwrap_or(body.len())));
+        tag.extend_from_slice(&body);
+        tag
+    }
+
+    fn id3v1(title: &str, artist: &str, year: &str) -> Vec<u8> {
+        let mut tag = b"TAG".to_vec();
+        for (field, len) in [(title, 30), (artist, 30), ("", 30), (year, 4), ("", 30)] {
+            let mut field = field.as_bytes().to_vec();
+            field.resize(len, 0);
+            tag.extend_from_slice(&field);
+        }
+        tag.push(255);
+        tag
+    }
+
+    fn assert_no_frame(result: Result<AudioMetadata, Error>) {
+        match result {
+            Err(Error::ParseAudio { reason }) => assert_eq!(reason, "no MPEG audio frame found"),
+            other => panic!("expected no frame, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn constant_bitrate_with_id3v2() {
+        let mut data = id3v2(
+            &[
+                ("TIT2", b"\x03Avsnitt 1: V\xc3\xa4rlden"),
+                ("TPE1", b"\x00OmV\xe4rlden"),
+                ("TYER", b"\x002024"),
+                ("COMM", b"\x00swe\x00Om avsnittet"),
+                ("APIC", b"\x00image/png\x00\x03\x00"),
+            ],
+            None,
+        );
+        // padding
+        data.extend_from_slice(&[0; 32]);
+        let tag_len = data.len();
+        data[6..10].copy_from_slice(&syncsafe_bytes(tag_len - 10));
+        data.extend_from_slice(&frames(100));
+
+        let metadata = AudioMetadata::from_bytes(&data).unwrap();
+        assert_eq!(metadata.byte_size, data.len() as u64);
+        assert_eq!(metadata.bitrate_kbps, 128);
+        assert_eq!(metadata.sample_rate, 44100);
+        assert_eq!(metadata.channels, 2);
+        assert_eq!(metadata.mpeg_version, "1");
+        assert_eq!(metadata.layer, 3);
+        assert!(!metadata.vbr);
+        let duration = (100 * FRAME_LEN * 8) as f64 / 128_000.0;
+        assert!((metadata.duration_secs - duration).abs() < 1e-9);
+        assert_eq!(
+            metadata.id3,
+            Some(Id3Tags {
+                version: "2.3.0".into(),
+                title: Some("Avsnitt 1: Världen".into()),
+                artist: Some("OmVärlden".into()),
+                album: None,
+                date: Some("2024".into()),
+                genre: None,
+                comment: Some("Om avsnittet".into()),
+            })
+        );
+    }
+
+    #[test]
+    fn id3v1_only() {
+        let mut data = frames(50);
+        data.extend_from_slice(&id3v1("Avsnitt 2", "OmVärlden", "2023"));
+
+        let metadata = AudioMetadata::from_bytes(&data).unwrap();
+        // the tag isn't audio
+        let duration = (50 * FRAME_LEN * 8) as f64 / 128_000.0;
+        assert!((metadata.duration_secs - duration).abs() < 1e-9);
+        let id3 = metadata.id3.unwrap();
+        assert_eq!(id3.version, "1");
+        assert_eq!(id3.title.as_deref(), Some("Avsnitt 2"));
+        // ID3v1 is Latin-1, the UTF-8 bytes are read as such
+        assert_eq!(id3.artist.as_deref(), Some("OmVÃ¤rlden"));
+        assert_eq!(id3.date.as_deref(), Some("2023"));
+        assert_eq!(id3.album, None);
+    }
+
+    #[test]
+    fn id3v1_fills_in_missing_id3v2_fields() {
+        let mut data = id3v2(&[("TIT2", b"\x00Avsnitt 3")], None);
+        data.extend_from_slice(&frames(20));
+        data.extend_from_slice(&id3v1("Another title", "OmVarlden", "2022"));
+
+        let id3 = AudioMetadata::from_bytes(&data).unwrap().id3.unwrap();
+        assert_eq!(id3.version, "2.3.0");
+        assert_eq!(id3.title.as_deref(), Some("Avsnitt 3"));
+        assert_eq!(id3.artist.as_deref(), Some("OmVarlden"));
+        assert_eq!(id3.date.as_deref(), Some("2022"));
+    }
+
+    #[test]
+    fn truncated_tag() {
+        // the file ends inside the tag
+        let data = id3v2(&[("TIT2", b"\x00Avsnitt 4")], Some(10_000));
+        assert_no_frame(AudioMetadata::from_bytes(&data));
+
+        // a frame claiming more than is left of the tag is cut at its end
+        let mut data = id3v2(&[("TIT2", b"\x00Avsnitt 4")], None);
+        let size_at = 10 + 4;
+        data[size_at..size_at + 4].copy_from_slice(&1000u32.to_be_bytes());
+        data.extend_from_slice(&frames(20));
+        let metadata = AudioMetadata::from_bytes(&data).unwrap();
+        assert_eq!(metadata.id3.unwrap().title.as_deref(), Some("Avsnitt 4"));
+        assert_eq!(metadata.bitrate_kbps, 128);
+    }
+
+    #[test]
+    fn bad_sync_word() {
+        let mut data = frames(20);
+        for frame in data.chunks_mut(FRAME_LEN) {
+            frame[1] = 0x1b;
+        }
+        assert_no_frame(AudioMetadata::from_bytes(&data));
+        assert_no_frame(AudioMetadata::from_bytes(b"not audio at all"));
+        assert_no_frame(AudioMetadata::from_bytes(&[]));
+    }
+
+    #[test]
+    fn skips_data_before_the_first_frame() {
+        // a lone header not followed by another frame isn't taken for one
+        let mut data = vec![0x00, 0x11];
+        data.extend_from_slice(&HEADER);
+        data.extend_from_slice(&[0x22; 100]);
+        let junk = data.len();
+        data.extend_from_slice(&frames(10));
+
+        let metadata = AudioMetadata::from_bytes(&data).unwrap();
+        let duration = (10 * FRAME_LEN * 8) as f64 / 128_000.0;
+        assert!((metadata.duration_secs - duration).abs() < 1e-9);
+        assert_eq!(metadata.byte_size, (junk + 10 * FRAME_LEN) as u64);
+    }
+
+    #[test]
+    fn xing_frame() {
+        let mut data = frames(10);
+        // after the header and 32 bytes of stereo MPEG 1 side information
+        let xing = 4 + 32;
+        data[xing..xing + 4].copy_from_slice(b"Xing");
+        data[xing + 4..xing + 8].copy_from_slice(&1u32.to_be_bytes());
+        data[xing + 8..xing + 12].copy_from_slice(&1000u32.to_be_bytes());
+
+        let metadata = AudioMetadata::from_bytes(&data).unwrap();
+        assert!(metadata.vbr);
+        let duration = 1000.0 * 1152.0 / 44100.0;
+        assert!((metadata.duration_secs - duration).abs() < 1e-9);
+        let bitrate = (data.len() as f64 * 8.0 / duration / 1000.0).round() as u32;
+        assert_eq!(metadata.bitrate_kbps, bitrate);
+
+        // an Info header is written for constant bitrate
+        data[xing..xing + 4].copy_from_slice(b"Info");
+        let metadata = AudioMetadata::from_bytes(&data).unwrap();
+        assert!(!metadata.vbr);
+        assert!((metadata.duration_secs - duration).abs() < 1e-9);
+
+        // without the frame count the size gives the duration
+        data[xing + 4..xing + 8].copy_from_slice(&0u32.to_be_bytes());
+        let metadata = AudioMetadata::from_bytes(&data).unwrap();
+        assert_eq!(metadata.bitrate_kbps, 128);
+    }
+}
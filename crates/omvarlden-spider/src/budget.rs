@@ -0,0 +1,107 @@
+//! Bounding a crawl by pages, link depth, bytes and time.
+//!
+//! When a page or byte budget is used up the spider triggers its
+//! [`Shutdown`], so the crawl stops the same way as on a signal, with its state
+//! saved. The duration budget is left to a timer triggering the same
+//! `Shutdown`, as the crawl may wait on fetches for long. Fetches already
+//! scheduled still run, so a crawl may go a little over its budgets. The depth
+//! budget doesn't stop the crawl, links deeper than it are just not followed.
+//!
+//! Link depths are kept in memory only. A resumed crawl doesn't know the depth
+//! of the URLs queued in its saved state and takes them as start URLs, so the
+//! depth budget starts over from them.
+//!
+//! [`Shutdown`]: crate::shutdown::Shutdown
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CrawlBudget {
+    /// URLs to fetch, pages and episodes alike.
+    pub max_pages: Option<u64>,
+    /// Links to follow from the start URLs, which are at depth 0.
+    pub max_depth: Option<u32>,
+    /// Bytes of content to download.
+    pub max_bytes: Option<u64>,
+    /// Bytes of podcast audio to download.
+    pub max_audio_bytes: Option<u64>,
+    /// Running time. Budgets are checked after fetches, which may stall, so
+    /// the program running the crawl triggers the shutdown with a timer.
+    pub max_duration: Option<Duration>,
+}
+
+/// What has been used of a [`CrawlBudget`].
+#[derive(Debug)]
+pub struct BudgetTracker {
+    budget: CrawlBudget,
+    pages: AtomicU64,
+    bytes: AtomicU64,
+    audio_bytes: AtomicU64,
+    /// Link depth of every URL seen, only kept with a depth budget.
+    depths: Mutex<HashMap<String, u32>>,
+}
+
+impl BudgetTracker {
+    pub fn new(budget: CrawlBudget) -> Self {
+        Self {
+            budget,
+            pages: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+            audio_bytes: AtomicU64::new(0),
+            depths: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn record_fetch(&self) {
+        self.pages.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_bytes(&self, bytes: usize, audio: bool) {
+        self.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        if audio {
+            self.audio_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// Drop the links found on `url` that are deeper than the depth budget.
+    pub fn filter_links(&self, url: &str, links: &mut Vec<String>) {
+        let Some(max_depth) = self.budget.max_depth else {
+            return;
+        };
+        let mut depths = self.depths.lock().unwrap();
+        // urls not seen as links are start urls
+        let depth = depths.get(url).copied().unwrap_or_default() + 1;
+        if depth > max_depth {
+            links.clear();
+            return;
+        }
+        for link in links.iter() {
+            let link_depth = depths.entry(link.clone()).or_insert(depth);
+            *link_depth = (*link_depth).min(depth);
+        }
+    }
+
+    /// The name of the first fetch or byte budget that is used up, if any.
+    pub fn exceeded(&self) -> Option<&'static str> {
+        let used_up = |used: u64, max: Option<u64>| max.is_some_and(|max| used >= max);
+        if used_up(self.pages.load(Ordering::Relaxed), self.budget.max_pages) {
+            Some("pages")
+        } else if used_up(self.bytes.load(Ordering::Relaxed), self.budget.max_bytes) {
+            Some("bytes")
+        } else if used_up(
+            self.audio_bytes.load(Ordering::Relaxed),
+            self.budget.max_audio_bytes,
+        ) {
+            Some("audio bytes")
+        } else {
+            None
+        }
+    }
+}
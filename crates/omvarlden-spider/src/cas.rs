@@ -0,0 +1,228 @@
+//! Content-addressed storage of fetched pages and episodes.
+//!
+//! Content is written once to `cas/blobs/<xx>/<sha256>` and the usual output
+//! path is hard linked to the blob, so the same content reachable under several
+//! URLs, or downloaded again by a later run, only takes up space once. Blobs
+//! are made read-only so that writing to a linked path can't change them.
+//!
+//! `cas/index.jsonl` records which content each URL had, one line for every
+//! new or changed URL, so changed content can be detected between runs.
+
+use std::{
+    collections::HashMap,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use crate::files::{read_lines, sync_parent, tmp_path};
+use crate::Error;
+
+/// The directory of the store in the output directory.
+pub const CAS_DIR: &str = "cas";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PutOutcome {
+    /// The URL has not been stored before.
+    New,
+    /// The URL was stored before with the same content.
+    Unchanged,
+    /// The URL was stored before with different content.
+    Changed { previous: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stored {
+    pub sha256: String,
+    pub outcome: PutOutcome,
+    /// Whether the content was already in the blob store.
+    pub deduplicated: bool,
+}
+
+pub struct ContentStore {
+    output_path: PathBuf,
+    root: PathBuf,
+    index: Mutex<Index>,
+}
+
+struct Index {
+    hashes: HashMap<String, String>,
+    log: fs::File,
+}
+
+impl ContentStore {
+    /// Open the store in `output_path/cas`, creating it if needed.
+    pub fn open(output_path: &Path) -> Result<Self, Error> {
+        let root = output_path.join(CAS_DIR);
+        let blobs = root.join("blobs");
+        fs::create_dir_all(&blobs).map_err(|error| Error::IoCreate {
+            path: blobs.clone(),
+            source: error,
+        })?;
+        let index_path = root.join("index.jsonl");
+        let mut hashes = HashMap::new();
+        if index_path.exists() {
+            for line in read_lines(&index_path)? {
+                let entry: serde_json::Value = match serde_json::from_str(&line) {
+                    Ok(entry) => entry,
+                    Err(error) => {
+                        tracing::warn!(
+                            "skipping bad line in '{}': {}",
+                            index_path.display(),
+                            error
+                        );
+                        continue;
+                    }
+                };
+                if let (Some(url), Some(sha256)) = (entry["url"].as_str(), entry["sha256"].as_str())
+                {
+                    hashes.insert(url.to_string(), sha256.to_string());
+                }
+            }
+        }
+        tracing::info!(
+            "loaded {} urls from '{}'",
+            hashes.len(),
+            index_path.display()
+        );
+        let log = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&index_path)
+            .map_err(|error| Error::IoCreate {
+                path: index_path,
+                source: error,
+            })?;
+        Ok(Self {
+            output_path: output_path.to_path_buf(),
+            root,
+            index: Mutex::new(Index { hashes, log }),
+        })
+    }
+
+    pub fn blob_path(&self, sha256: &str) -> PathBuf {
+        self.root.join("blobs").join(&sha256[..2]).join(sha256)
+    }
+
+    /// The hash of the content last stored for `url`.
+    pub fn hash_of(&self, url: &str) -> Option<String> {
+        self.index.lock().unwrap().hashes.get(url).cloned()
+    }
+
+    /// Store `data` fetched from `url`, hashing to the hex SHA-256 `sha256`,
+    /// and link it to `link`.
+    pub fn put(&self, url: &str, data: &[u8], sha256: &str, link: &Path) -> Result<Stored, Error> {
+        let sha256 = sha256.to_string();
+        let blob = self.blob_path(&sha256);
+        let deduplicated = blob.exists();
+        if !deduplicated {
+            write_blob(&blob, data)?;
+        }
+        link_blob(&blob, link)?;
+
+        let mut index = self.index.lock().unwrap();
+        let outcome = match index.hashes.get(url) {
+            None => PutOutcome::New,
+            Some(previous) if *previous == sha256 => PutOutcome::Unchanged,
+            Some(previous) => PutOutcome::Changed {
+                previous: previous.clone(),
+            },
+        };
+        if outcome != PutOutcome::Unchanged {
+            let previous = match &outcome {
+                PutOutcome::Changed { previous } => {
+                    tracing::info!(url, previous, sha256, "content changed");
+                    Some(previous.as_str())
+                }
+                _ => None,
+            };
+            let entry = serde_json::json!({
+                "url": url,
+                "sha256": sha256,
+                "size": data.len(),
+                "previous": previous,
+                "path": link.strip_prefix(&self.output_path).unwrap_or(link),
+                "stored_at": chrono::Utc::now().to_rfc3339(),
+            });
+            let index_path = self.root.join("index.jsonl");
+            serde_json::to_writer(&mut index.log, &entry).map_err(|error| {
+                Error::Serialization {
+                    path: index_path.clone(),
+                    source: error,
+                }
+            })?;
+            index.log.write_all(b"\n").map_err(|error| Error::IoWrite {
+                path: index_path,
+                source: error,
+            })?;
+            index.hashes.insert(url.to_string(), sha256.clone());
+        }
+        Ok(Stored {
+            sha256,
+            outcome,
+            deduplicated,
+        })
+    }
+}
+
+fn write_blob(blob: &Path, data: &[u8]) -> Result<(), Error> {
+    let write_error = |error| Error::IoWrite {
+        path: blob.to_path_buf(),
+        source: error,
+    };
+    if let Some(parent) = blob.parent() {
+        fs::create_dir_all(parent).map_err(|error| Error::IoCreate {
+            path: parent.to_path_buf(),
+            source: error,
+        })?;
+    }
+    // unique per write, the same content may be stored concurrently
+    let tmp = tmp_path(blob);
+    let mut file = fs::File::create(&tmp).map_err(|error| Error::IoCreate {
+        path: tmp.clone(),
+        source: error,
+    })?;
+    file.write_all(data).map_err(write_error)?;
+    file.sync_all().map_err(write_error)?;
+    let mut permissions = file.metadata().map_err(write_error)?.permissions();
+    permissions.set_readonly(true);
+    fs::set_permissions(&tmp, permissions).map_err(write_error)?;
+    if let Err(error) = fs::rename(&tmp, blob) {
+        let _ = fs::remove_file(&tmp);
+        return Err(write_error(error));
+    }
+    sync_parent(blob);
+    Ok(())
+}
+
+/// Replace `link` with a hard link to `blob`, or a copy where hard links aren't supported.
+///
+/// The link is made next to `link` and renamed over it, so `link` is never missing or partial.
+fn link_blob(blob: &Path, link: &Path) -> Result<(), Error> {
+    let link_error = |error| Error::IoWrite {
+        path: link.to_path_buf(),
+        source: error,
+    };
+    let tmp = tmp_path(link);
+    if let Err(error) = fs::hard_link(blob, &tmp) {
+        tracing::warn!(
+            "can't hard link '{}', copying instead: {}",
+            link.display(),
+            error
+        );
+        let copied = fs::copy(blob, &tmp)
+            .and_then(|_| fs::File::open(&tmp))
+            .and_then(|file| file.sync_all());
+        if let Err(error) = copied {
+            let _ = fs::remove_file(&tmp);
+            return Err(link_error(error));
+        }
+    }
+    if let Err(error) = fs::rename(&tmp, link) {
+        let _ = fs::remove_file(&tmp);
+        return Err(link_error(error));
+    }
+    sync_parent(link);
+    Ok(())
+}
@@ -0,0 +1,353 @@
+//! Normalizing the date strings found on omvarlden.se and libsyn to ISO 8601.
+//!
+//! Handles ISO 8601/RFC 3339, RFC 2822, English ("Mar 12, 2023") and Swedish
+//! ("12 mars 2023", "igår", "uppdaterad 14:05") dates, with or without a time.
+//! Dates without a time of day are placed at midnight. Everything is
+//! interpreted in and converted to Europe/Stockholm. Relative dates are resolved
+//! against the time the page was fetched, so they are parsed when fetching.
+//! Times skipped when the clocks are put forward are moved forward an hour,
+//! times repeated when they are put back are taken the first time.
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use chrono_tz::{Europe::Stockholm, Tz};
+
+use crate::Error;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParsedDate {
+    pub datetime: DateTime<Tz>,
+    /// Whether the original string included a time of day.
+    pub has_time: bool,
+    pub original: String,
+}
+
+impl ParsedDate {
+    pub fn iso(&self) -> String {
+        self.datetime.to_rfc3339()
+    }
+
+    pub fn date(&self) -> NaiveDate {
+        self.datetime.date_naive()
+    }
+}
+
+/// Parse `raw` found on a page fetched at `fetched_at`, resolving relative
+/// dates ("idag", "igår") against it.
+pub fn parse_date(raw: &str, fetched_at: DateTime<Utc>) -> Result<ParsedDate, Error> {
+    parse_date_relative_to(raw, fetched_at.with_timezone(&Stockholm))
+}
+
+/// Parse `raw`, resolving relative dates ("idag", "igår") against `now`.
+pub fn parse_date_relative_to(raw: &str, now: DateTime<Tz>) -> Result<ParsedDate, Error> {
+    let unparseable = || Error::ParseDate {
+        input: raw.to_string(),
+    };
+    let trimmed = raw.trim();
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(trimmed) {
+        return Ok(ParsedDate {
+            datetime: datetime.with_timezone(&Stockholm),
+            has_time: true,
+            original: raw.to_string(),
+        });
+    }
+    if let Ok(datetime) = DateTime::parse_from_rfc2822(trimmed) {
+        return Ok(ParsedDate {
+            datetime: datetime.with_timezone(&Stockholm),
+            has_time: true,
+            original: raw.to_string(),
+        });
+    }
+
+    let normalized = normalize(raw);
+
+    let (date, time) = parse_local(&normalized, now.date_naive()).ok_or_else(unparseable)?;
+    let naive = NaiveDateTime::new(date, time.unwrap_or(NaiveTime::MIN));
+    let datetime = Stockholm
+        .from_local_datetime(&naive)
+        .earliest()
+        // skipped when the clocks were put forward
+        .or_else(|| {
+            Stockholm
+                .from_local_datetime(&(naive + Duration::hours(1)))
+                .earliest()
+        })
+        .ok_or_else(unparseable)?;
+    Ok(ParsedDate {
+        datetime,
+        has_time: time.is_some(),
+        original: raw.to_string(),
+    })
+}
+
+/// Lowercase, collapse whitespace and drop prefixes like "Publicerad" and "kl.".
+fn normalize(raw: &str) -> String {
+    let lowered = raw.trim().to_lowercase();
+    let mut words: Vec<&str> = lowered
+        .split_whitespace()
+        .filter(|word| !matches!(*word, "kl" | "kl." | "klockan" | "at" | "-" | "|"))
+        .collect();
+    while let Some(first) = words.first() {
+        let first = first.trim_end_matches(':');
+        if matches!(
+            first,
+            "publicerad" | "publicerat" | "uppdaterad" | "uppdaterat" | "published" | "updated"
+        ) || is_weekday(first.trim_end_matches(','))
+        {
+            words.remove(0);
+        } else {
+            break;
+        }
+    }
+    // "i dag" / "i går"
+    let mut joined = Vec::with_capacity(words.len());
+    let mut words = words.into_iter().peekable();
+    while let Some(word) = words.next() {
+        let relative = match (word, words.peek().map(|next| next.trim_end_matches(','))) {
+            ("i", Some("dag")) => "idag",
+            ("i", Some("går")) => "igår",
+            ("i", Some("förrgår")) => "förrgår",
+            _ => {
+                joined.push(word);
+                continue;
+            }
+        };
+        words.next();
+        joined.push(relative);
+    }
+    joined.join(" ")
+}
+
+fn parse_local(text: &str, today: NaiveDate) -> Option<(NaiveDate, Option<NaiveTime>)> {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let (time, date_words) = match words.split_last() {
+        Some((last, rest)) => match parse_time(last) {
+            Some(time) => (Some(time), rest),
+            None => (None, &words[..]),
+        },
+        None => return None,
+    };
+    let date_words: Vec<&str> = date_words
+        .iter()
+        .map(|word| word.trim_matches(','))
+        .filter(|word| !word.is_empty())
+        .collect();
+
+    let date = match date_words.as_slice() {
+        // "uppdaterad 14:05"
+        [] => time.map(|_| today)?,
+        ["idag"] | ["today"] => today,
+        ["igår"] | ["yesterday"] => today.pred_opt()?,
+        ["förrgår"] => today.pred_opt()?.pred_opt()?,
+        [date] => parse_numeric_date(date)?,
+        // "12 mars 2023", "12 march 2023", "12. mars 2023"
+        [day, month, year] if parse_day(day).is_some() => {
+            NaiveDate::from_ymd_opt(year.parse().ok()?, month_number(month)?, parse_day(day)?)?
+        }
+        // "march 12 2023"
+        [month, day, year] => {
+            NaiveDate::from_ymd_opt(year.parse().ok()?, month_number(month)?, parse_day(day)?)?
+        }
+        // "12 mars", in the current year
+        [day, month] if parse_day(day).is_some() => {
+            NaiveDate::from_ymd_opt(today.year(), month_number(month)?, parse_day(day)?)?
+        }
+        _ => return None,
+    };
+    Some((date, time))
+}
+
+fn parse_numeric_date(date: &str) -> Option<NaiveDate> {
+    ["%Y-%m-%d", "%Y/%m/%d", "%Y%m%d", "%d.%m.%Y"]
+        .iter()
+        .find_map(|format| NaiveDate::parse_from_str(date, format).ok())
+        .or_else(|| {
+            NaiveDateTime::parse_from_str(date, "%Y-%m-%dT%H:%M:%S")
+                .ok()
+                .map(|datetime| datetime.date())
+        })
+}
+
+fn parse_time(time: &str) -> Option<NaiveTime> {
+    let time = time.replace('.', ":");
+    NaiveTime::parse_from_str(&time, "%H:%M")
+        .or_else(|_| NaiveTime::parse_from_str(&time, "%H:%M:%S"))
+        .ok()
+}
+
+fn parse_day(day: &str) -> Option<u32> {
+    let day = day.trim_end_matches('.');
+    let day = ["st", "nd", "rd", "th", ":e", ":a"]
+        .iter()
+        .find_map(|suffix| day.strip_suffix(suffix))
+        .unwrap_or(day);
+    day.parse().ok().filter(|day| (1..=31).contains(day))
+}
+
+fn month_number(month: &str) -> Option<u32> {
+    let month = month.trim_end_matches('.');
+    let number = match month {
+        "januari" | "january" | "jan" => 1,
+        "februari" | "february" | "feb" => 2,
+        "mars" | "march" | "mar" => 3,
+        "april" | "apr" => 4,
+        "maj" | "may" => 5,
+        "juni" | "june" | "jun" => 6,
+        "juli" | "july" | "jul" => 7,
+        "augusti" | "august" | "aug" => 8,
+        "september" | "sept" | "sep" => 9,
+        "oktober" | "october" | "okt" | "oct" => 10,
+        "november" | "nov" => 11,
+        "december" | "dec" => 12,
+        _ => return None,
+    };
+    Some(number)
+}
+
+fn is_weekday(word: &str) -> bool {
+    matches!(
+        word,
+        "måndag"
+            | "tisdag"
+            | "onsdag"
+            | "torsdag"
+            | "fredag"
+            | "lördag"
+            | "söndag"
+            | "monday"
+            | "tuesday"
+            | "wednesday"
+            | "thursday"
+            | "friday"
+            | "saturday"
+            | "sunday"
+            | "mon"
+            | "tue"
+            | "wed"
+            | "thu"
+            | "fri"
+            | "sat"
+            | "sun"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn now() -> DateTime<Tz> {
+        Stockholm.with_ymd_and_hms(2023, 3, 12, 10, 0, 0).unwrap()
+    }
+
+    fn iso(raw: &str, now: DateTime<Tz>) -> String {
+        parse_date_relative_to(raw, now).unwrap().iso()
+    }
+
+    #[test]
+    fn swedish_dates() {
+        let date = parse_date_relative_to("12 mars 2023", now()).unwrap();
+        assert_eq!(date.iso(), "2023-03-12T00:00:00+01:00");
+        assert!(!date.has_time);
+        assert_eq!(date.original, "12 mars 2023");
+        assert_eq!(
+            iso("Publicerad: måndag 3 juli 2023 kl. 14.05", now()),
+            "2023-07-03T14:05:00+02:00"
+        );
+        assert_eq!(iso("12 mars", now()), "2023-03-12T00:00:00+01:00");
+    }
+
+    #[test]
+    fn english_month_names() {
+        let months = [
+            "January",
+            "February",
+            "March",
+            "April",
+            "May",
+            "June",
+            "July",
+            "August",
+            "September",
+            "October",
+            "November",
+            "December",
+        ];
+        for (i, month) in months.iter().enumerate() {
+            let date = parse_date_relative_to(&format!("2 {} 2023", month), now()).unwrap();
+            assert_eq!(
+                date.date(),
+                NaiveDate::from_ymd_opt(2023, i as u32 + 1, 2).unwrap()
+            );
+        }
+        assert_eq!(iso("Mar 12, 2023", now()), "2023-03-12T00:00:00+01:00");
+        assert_eq!(
+            iso("Sunday, March 12th 2023 at 14:05", now()),
+            "2023-03-12T14:05:00+01:00"
+        );
+    }
+
+    #[test]
+    fn relative_dates() {
+        assert_eq!(iso("igår", now()), "2023-03-11T00:00:00+01:00");
+        assert_eq!(iso("I går, 09:30", now()), "2023-03-11T09:30:00+01:00");
+        assert_eq!(iso("idag", now()), "2023-03-12T00:00:00+01:00");
+        assert_eq!(iso("i förrgår", now()), "2023-03-10T00:00:00+01:00");
+        let date = parse_date_relative_to("uppdaterad 14:05", now()).unwrap();
+        assert_eq!(date.iso(), "2023-03-12T14:05:00+01:00");
+        assert!(date.has_time);
+    }
+
+    #[test]
+    fn relative_to_fetch_time_in_stockholm() {
+        // already the 26th in Stockholm
+        let fetched_at = Utc.with_ymd_and_hms(2023, 3, 25, 23, 30, 0).unwrap();
+        assert_eq!(
+            parse_date("idag", fetched_at).unwrap().iso(),
+            "2023-03-26T00:00:00+01:00"
+        );
+    }
+
+    #[test]
+    fn daylight_saving_time() {
+        // clocks put forward from 02:00 to 03:00 on 26 March 2023
+        assert_eq!(
+            iso("25 mars 2023 12:00", now()),
+            "2023-03-25T12:00:00+01:00"
+        );
+        assert_eq!(
+            iso("26 mars 2023 12:00", now()),
+            "2023-03-26T12:00:00+02:00"
+        );
+        assert_eq!(
+            iso("26 mars 2023 02:30", now()),
+            "2023-03-26T03:30:00+02:00"
+        );
+        let after = Stockholm.with_ymd_and_hms(2023, 3, 27, 1, 0, 0).unwrap();
+        assert_eq!(iso("igår", after), "2023-03-26T00:00:00+01:00");
+        // and back from 03:00 to 02:00 on 29 October 2023
+        assert_eq!(
+            iso("29 oktober 2023 02:30", now()),
+            "2023-10-29T02:30:00+02:00"
+        );
+        assert_eq!(
+            iso("2023-10-29T01:30:00Z", now()),
+            "2023-10-29T02:30:00+01:00"
+        );
+    }
+
+    #[test]
+    fn whole_words_only() {
+        assert_eq!(normalize("I dagens läge"), "i dagens läge");
+        assert_eq!(normalize("i går"), "igår");
+        assert!(parse_date_relative_to("i dagens läge", now()).is_err());
+    }
+
+    #[test]
+    fn unparseable() {
+        for raw in ["", "snart", "31 februari 2023", "12 smarch 2023", "25:00"] {
+            match parse_date_relative_to(raw, now()) {
+                Err(Error::ParseDate { input }) => assert_eq!(input, raw),
+                result => panic!("{:?} parsed as {:?}", raw, result),
+            }
+        }
+    }
+}
@@ -0,0 +1,264 @@
+//! A manifest of the downloaded podcast episodes, for building speech corpora.
+//!
+//! Each row joins an episode's `podd_meta` JSON with its mp3 file, and records
+//! whether the download is complete: the file exists, its size matches the
+//! size recorded when it was downloaded and the audio could be read.
+//!
+//! The mp3 files are read from the output directory, so only the storages with
+//! one file per key, `tree` and `content-addressed`, are supported.
+
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use reqwest::Url;
+
+use crate::files::{files_with_suffix, read_json, sha256_file, AtomicFile};
+use crate::omvarlden;
+use crate::storage::check_tree_layout;
+use crate::Error;
+
+pub const DEFAULT_LICENSE: &str =
+    "Utvecklingssamtalet, © OmVärlden/Sida. Check the terms of use before redistribution.";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EpisodeStatus {
+    /// The mp3 exists and matches the recorded size.
+    Complete,
+    /// The mp3 has not been downloaded.
+    Missing,
+    /// The mp3 size differs from the size recorded at download.
+    Incomplete,
+    /// The mp3 could not be read as MPEG audio.
+    Invalid,
+    /// The mp3 exists but no audio metadata was recorded for it.
+    Unverified,
+    /// The mp3 exists but couldn't be read.
+    Unreadable,
+}
+
+impl EpisodeStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Complete => "complete",
+            Self::Missing => "missing",
+            Self::Incomplete => "incomplete",
+            Self::Invalid => "invalid",
+            Self::Unverified => "unverified",
+            Self::Unreadable => "unreadable",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EpisodeRow {
+    pub status: EpisodeStatus,
+    /// Why the mp3 is invalid or unreadable.
+    pub error: Option<String>,
+    pub audio_path: Option<String>,
+    pub sha256: Option<String>,
+    pub duration_secs: Option<f64>,
+    pub byte_size: Option<u64>,
+    pub title: Option<String>,
+    pub release_date: Option<String>,
+    pub description: Option<String>,
+    pub meta_url: Option<String>,
+    pub mp3_url: Option<String>,
+    pub article_urls: Vec<String>,
+    pub license: String,
+}
+
+impl EpisodeRow {
+    const CSV_HEADER: [&'static str; 13] = [
+        "status",
+        "error",
+        "audio_path",
+        "sha256",
+        "duration_secs",
+        "byte_size",
+        "title",
+        "release_date",
+        "description",
+        "meta_url",
+        "mp3_url",
+        "article_urls",
+        "license",
+    ];
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "status": self.status.as_str(),
+            "error": self.error,
+            "audio_path": self.audio_path,
+            "sha256": self.sha256,
+            "duration_secs": self.duration_secs,
+            "byte_size": self.byte_size,
+            "title": self.title,
+            "release_date": self.release_date,
+            "description": self.description,
+            "meta_url": self.meta_url,
+            "mp3_url": self.mp3_url,
+            "article_urls": self.article_urls,
+            "license": self.license,
+        })
+    }
+
+    fn to_csv_record(&self) -> [String; 13] {
+        let opt = |value: &Option<String>| value.clone().unwrap_or_default();
+        [
+            self.status.as_str().to_string(),
+            opt(&self.error),
+            opt(&self.audio_path),
+            opt(&self.sha256),
+            self.duration_secs
+                .map(|d| d.to_string())
+                .unwrap_or_default(),
+            self.byte_size.map(|b| b.to_string()).unwrap_or_default(),
+            opt(&self.title),
+            opt(&self.release_date),
+            opt(&self.description),
+            opt(&self.meta_url),
+            opt(&self.mp3_url),
+            self.article_urls.join(" "),
+            self.license.clone(),
+        ]
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EpisodeManifest {
+    pub rows: Vec<EpisodeRow>,
+    /// Metadata files that couldn't be read, and were skipped.
+    pub unreadable: Vec<PathBuf>,
+}
+
+/// Collect one row per episode in `output_path/podd_meta`.
+pub fn collect_episodes(output_path: &Path, license: &str) -> Result<EpisodeManifest, Error> {
+    check_tree_layout(output_path, "episode manifest")?;
+    let mut manifest = EpisodeManifest::default();
+    for meta_path in files_with_suffix(&output_path.join("podd_meta"), ".json") {
+        let json = match read_json(&meta_path) {
+            Ok(json) => json,
+            Err(error) => {
+                tracing::warn!("skipping: {:#}", error);
+                manifest.unreadable.push(meta_path);
+                continue;
+            }
+        };
+        manifest.rows.push(episode_row(output_path, &json, license));
+    }
+    Ok(manifest)
+}
+
+fn episode_row(output_path: &Path, json: &serde_json::Value, license: &str) -> EpisodeRow {
+    let string = |key: &str| json[key].as_str().map(str::to_string);
+    let mp3_url = string("mp3_url");
+    let audio_path = mp3_url
+        .as_deref()
+        .and_then(|mp3_url| Url::parse(mp3_url).ok())
+        .map(|mp3_url| omvarlden::podd_path(output_path, &mp3_url));
+    let audio = &json["audio"];
+
+    let mut error = None;
+    let (status, sha256, byte_size) = match audio_path.as_deref().filter(|p| p.is_file()) {
+        None => (EpisodeStatus::Missing, None, None),
+        Some(path) => match sha256_file(path) {
+            Err(read_error) => {
+                tracing::warn!("{:#}", read_error);
+                error = Some(format!("{:#}", read_error));
+                (EpisodeStatus::Unreadable, None, None)
+            }
+            Ok((sha256, byte_size)) => {
+                let status = if audio.is_null() {
+                    EpisodeStatus::Unverified
+                } else if !audio["error"].is_null() {
+                    error = audio["error"].as_str().map(str::to_string);
+                    EpisodeStatus::Invalid
+                } else if audio["byte_size"].as_u64() != Some(byte_size) {
+                    EpisodeStatus::Incomplete
+                } else {
+                    EpisodeStatus::Complete
+                };
+                (status, Some(sha256), Some(byte_size))
+            }
+        },
+    };
+
+    EpisodeRow {
+        status,
+        error,
+        audio_path: audio_path.map(|path| relative(output_path, &path)),
+        sha256,
+        duration_secs: audio["duration_secs"].as_f64(),
+        byte_size,
+        title: string("title"),
+        release_date: string("release_date").or_else(|| string("release_date_raw")),
+        description: string("description").map(|d| d.trim().to_string()),
+        meta_url: string("meta_url"),
+        mp3_url,
+        article_urls: json["articles"]
+            .as_array()
+            .map(|articles| {
+                articles
+                    .iter()
+                    .filter_map(|article| article["url"].as_str().map(str::to_string))
+                    .collect()
+            })
+            .unwrap_or_default(),
+        license: license.to_string(),
+    }
+}
+
+pub fn write_jsonl(path: &Path, rows: &[EpisodeRow]) -> Result<(), Error> {
+    let mut file = AtomicFile::create(path)?;
+    for row in rows {
+        serde_json::to_writer(&mut file, &row.to_json()).map_err(|error| Error::Serialization {
+            path: path.to_path_buf(),
+            source: error,
+        })?;
+        file.write_all(b"\n").map_err(|error| Error::IoWrite {
+            path: path.to_path_buf(),
+            source: error,
+        })?;
+    }
+    file.commit()
+}
+
+pub fn write_csv(path: &Path, rows: &[EpisodeRow]) -> Result<(), Error> {
+    let csv_error = |error: csv::Error| Error::IoWrite {
+        path: path.to_path_buf(),
+        source: error.into(),
+    };
+    let mut writer = csv::Writer::from_writer(AtomicFile::create(path)?);
+    writer
+        .write_record(EpisodeRow::CSV_HEADER)
+        .map_err(csv_error)?;
+    for row in rows {
+        writer
+            .write_record(row.to_csv_record())
+            .map_err(csv_error)?;
+    }
+    writer
+        .into_inner()
+        .map_err(|error| Error::IoWrite {
+            path: path.to_path_buf(),
+            source: error.into_error(),
+        })?
+        .commit()
+}
+
+fn relative(output_path: &Path, path: &Path) -> String {
+    path.strip_prefix(output_path)
+        .unwrap_or(path)
+        .display()
+        .to_string()
+}
+
+/// Default manifest paths inside the output directory.
+pub fn default_paths(output_path: &Path) -> (PathBuf, PathBuf) {
+    (
+        output_path.join("episodes.jsonl"),
+        output_path.join("episodes.csv"),
+    )
+}
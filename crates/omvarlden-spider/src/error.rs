@@ -1,79 +1,218 @@
 use std::{error::Error as StdError, fmt, io, path::PathBuf};
 
+use serde::ser::{Serialize, SerializeMap, Serializer};
+
 #[derive(Debug)]
 pub enum Error {
-    CantCreateDir {
+    /// Sending a request or receiving its response failed.
+    Fetch {
+        url: String,
+        source: reqwest::Error,
+    },
+    /// The server answered with an error status.
+    HttpStatus {
+        url: String,
+        status: reqwest::StatusCode,
+    },
+    ParseDate {
+        input: String,
+    },
+    ParseAudio {
+        reason: String,
+    },
+    ParseJson {
         path: PathBuf,
-        error: io::Error,
+        source: serde_json::Error,
     },
-    CantCanonicalizePath {
+    IoRead {
         path: PathBuf,
-        error: io::Error,
+        source: io::Error,
     },
-    CantCreateHttpClient(reqwest::Error),
-    FailedToGetData {
-        url: String,
-        error: reqwest::Error,
+    /// Creating a file or directory failed.
+    IoCreate {
+        path: PathBuf,
+        source: io::Error,
     },
-    FailedWritingFile {
+    IoWrite {
         path: PathBuf,
-        error: io::Error,
+        source: io::Error,
     },
-    FailedWritingJson {
+    Serialization {
         path: PathBuf,
-        error: serde_json::Error,
+        source: serde_json::Error,
     },
-    ScrapeError {
-        url: String,
-        error: reqwest::Error,
+    /// Something couldn't be set up as configured.
+    Config {
+        what: &'static str,
+        source: Box<dyn StdError + Send + Sync>,
     },
-    RequestReturnedError {
-        url: String,
-        status_code: reqwest::StatusCode,
+    #[cfg(feature = "sqlite")]
+    Sqlite {
+        path: PathBuf,
+        source: rusqlite::Error,
     },
-    Unknown(String),
 }
 
+/// The message, without the source. The alternate form, `{:#}`, adds the
+/// sources, like `anyhow` does: "Failed to read 'a.json': No such file".
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::CantCanonicalizePath { path, error } => f.write_fmt(format_args!(
-                "Can't canonicalize '{}': {}",
-                path.display(),
-                error
-            )),
-            Self::CantCreateDir { path, error } => f.write_fmt(format_args!(
-                "Can't create dir '{}': {}",
-                path.display(),
-                error
-            )),
-            Self::CantCreateHttpClient(error) => {
-                f.write_fmt(format_args!("Can't create http client: {}", error))
+            Self::Fetch { url, .. } => f.write_fmt(format_args!("Failed fetching '{}'", url))?,
+            Self::HttpStatus { url, status } => {
+                f.write_fmt(format_args!("The request to '{}' returned {}", url, status))?
+            }
+            Self::ParseDate { input } => {
+                f.write_fmt(format_args!("Can't parse date '{}'", input))?
             }
-            Self::FailedToGetData { url, error } => f.write_fmt(format_args!(
-                "Failed getting data from '{}': {}",
-                url, error
-            )),
-            Self::FailedWritingFile { path, error } => f.write_fmt(format_args!(
-                "Failed to write file '{}': {}",
-                path.display(),
-                error
-            )),
-            Self::FailedWritingJson { path, error } => f.write_fmt(format_args!(
-                "Failed to write JSON to '{}': {}",
-                path.display(),
-                error
-            )),
-            Self::RequestReturnedError { url, status_code } => f.write_fmt(format_args!(
-                "The request to '{}' returned {}",
-                url, status_code
-            )),
-            Self::ScrapeError { url, error } => {
-                f.write_fmt(format_args!("Failed fetching '{}': {}", url, error))
+            Self::ParseAudio { reason } => {
+                f.write_fmt(format_args!("Invalid audio: {}", reason))?
+            }
+            Self::ParseJson { path, .. } => {
+                f.write_fmt(format_args!("Failed to parse JSON in '{}'", path.display()))?
+            }
+            Self::IoRead { path, .. } => {
+                f.write_fmt(format_args!("Failed to read '{}'", path.display()))?
+            }
+            Self::IoCreate { path, .. } => {
+                f.write_fmt(format_args!("Can't create '{}'", path.display()))?
+            }
+            Self::IoWrite { path, .. } => {
+                f.write_fmt(format_args!("Failed to write '{}'", path.display()))?
+            }
+            Self::Serialization { path, .. } => f.write_fmt(format_args!(
+                "Failed to serialize JSON for '{}'",
+                path.display()
+            ))?,
+            Self::Config { what, .. } => f.write_fmt(format_args!("Can't configure {}", what))?,
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite { path, .. } => {
+                f.write_fmt(format_args!("SQLite error in '{}'", path.display()))?
+            }
+        }
+        if f.alternate() {
+            let mut source = self.source();
+            while let Some(error) = source {
+                f.write_fmt(format_args!(": {}", error))?;
+                source = error.source();
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Error {
+    /// A stable, machine-readable code of the variant, for counting errors
+    /// by kind and in failure logs.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Fetch { .. } => "fetch",
+            Self::HttpStatus { .. } => "http_status",
+            Self::ParseDate { .. } => "parse_date",
+            Self::ParseAudio { .. } => "parse_audio",
+            Self::ParseJson { .. } => "parse_json",
+            Self::IoRead { .. } => "io_read",
+            Self::IoCreate { .. } => "io_create",
+            Self::IoWrite { .. } => "io_write",
+            Self::Serialization { .. } => "serialization",
+            Self::Config { .. } => "config",
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite { .. } => "sqlite",
+        }
+    }
+
+    /// Whether trying again later may succeed.
+    ///
+    /// Network errors, server errors, rate limiting and filesystem errors like
+    /// a full disk are retryable, while client errors like 404 and content
+    /// that can't be parsed are not.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::Fetch { source, .. } => !source.is_builder(),
+            Self::HttpStatus { status, .. } => {
+                status.is_server_error()
+                    || *status == reqwest::StatusCode::REQUEST_TIMEOUT
+                    || *status == reqwest::StatusCode::TOO_MANY_REQUESTS
             }
-            Self::Unknown(msg) => f.write_fmt(format_args!("Unknown error: {}", msg)),
+            Self::IoRead { .. } | Self::IoCreate { .. } | Self::IoWrite { .. } => true,
+            Self::ParseDate { .. }
+            | Self::ParseAudio { .. }
+            | Self::ParseJson { .. }
+            | Self::Serialization { .. }
+            | Self::Config { .. } => false,
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite { source, .. } => matches!(
+                source.sqlite_error_code(),
+                Some(rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked)
+            ),
+        }
+    }
+
+    /// The HTTP status of a failed request, if it got a response.
+    pub fn status_code(&self) -> Option<u16> {
+        match self {
+            Self::HttpStatus { status, .. } => Some(status.as_u16()),
+            Self::Fetch { source, .. } => source.status().map(|status| status.as_u16()),
+            _ => None,
+        }
+    }
+
+    /// The URL the error happened for, if any.
+    pub fn url(&self) -> Option<&str> {
+        match self {
+            Self::Fetch { url, .. } | Self::HttpStatus { url, .. } => Some(url),
+            _ => None,
+        }
+    }
+
+    /// The file the error happened for, if any.
+    pub fn path(&self) -> Option<&PathBuf> {
+        match self {
+            Self::ParseJson { path, .. }
+            | Self::IoRead { path, .. }
+            | Self::IoCreate { path, .. }
+            | Self::IoWrite { path, .. }
+            | Self::Serialization { path, .. } => Some(path),
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite { path, .. } => Some(path),
+            _ => None,
         }
     }
 }
 
-impl StdError for Error {}
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Self::Fetch { source, .. } => Some(source),
+            Self::ParseJson { source, .. } | Self::Serialization { source, .. } => Some(source),
+            Self::IoRead { source, .. }
+            | Self::IoCreate { source, .. }
+            | Self::IoWrite { source, .. } => Some(source),
+            Self::Config { source, .. } => Some(source.as_ref()),
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite { source, .. } => Some(source),
+            Self::HttpStatus { .. } | Self::ParseDate { .. } | Self::ParseAudio { .. } => None,
+        }
+    }
+}
+
+/// Serialized as a map of the code, message with its sources, whether it is
+/// retryable and the URL, HTTP status and path when known.
+impl Serialize for Error {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("code", self.code())?;
+        map.serialize_entry("message", &format!("{:#}", self))?;
+        map.serialize_entry("retryable", &self.is_retryable())?;
+        if let Some(url) = self.url() {
+            map.serialize_entry("url", url)?;
+        }
+        if let Some(status) = self.status_code() {
+            map.serialize_entry("status", &status)?;
+        }
+        if let Some(path) = self.path() {
+            map.serialize_entry("path", path)?;
+        }
+        map.end()
+    }
+}
@@ -0,0 +1,204 @@
+//! A dead-letter log of the URLs that failed, for retrying them later.
+//!
+//! `crawl_failures.jsonl` in the output directory gets a line for every failed
+//! attempt to scrape a URL or to process an item from it, with the error (see
+//! the `Serialize` implementation of [`Error`]) and how many times the URL has
+//! failed so far. When a
+//! URL that failed is fetched successfully, a line marking it as resolved is
+//! appended, so that [`pending_failures`] only gives the URLs still failing.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use chrono::{DateTime, Utc};
+
+use crate::files::read_lines;
+use crate::Error;
+
+pub const FAILURES_FILE: &str = "crawl_failures.jsonl";
+
+/// The stage of the crawl a URL failed in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    Scrape,
+    Process,
+}
+
+impl Stage {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Scrape => "scrape",
+            Self::Process => "process",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Failure {
+    pub url: String,
+    pub stage: Stage,
+    pub error: serde_json::Value,
+    /// Failed attempts of the URL, this one included.
+    pub attempts: u32,
+    pub failed_at: DateTime<Utc>,
+}
+
+impl Failure {
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "url": self.url,
+            "stage": self.stage.as_str(),
+            "error": self.error,
+            "attempts": self.attempts,
+            "failed_at": self.failed_at.to_rfc3339(),
+        })
+    }
+}
+
+pub struct FailureLog {
+    path: PathBuf,
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    /// `None` in dry runs.
+    log: Option<fs::File>,
+    /// Failed attempts by URL, over all runs.
+    attempts: HashMap<String, u32>,
+    /// URLs whose last attempt failed.
+    pending: HashSet<String>,
+}
+
+impl FailureLog {
+    /// Open `output_path/crawl_failures.jsonl` for appending, continuing the
+    /// attempt counts of earlier runs.
+    pub fn open(output_path: &Path) -> Result<Self, Error> {
+        let mut failures = Self::dry_run(output_path)?;
+        let log = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&failures.path)
+            .map_err(|error| Error::IoCreate {
+                path: failures.path.clone(),
+                source: error,
+            })?;
+        failures.inner.get_mut().unwrap().log = Some(log);
+        Ok(failures)
+    }
+
+    /// Read the failures in `output_path`, without recording new ones.
+    pub fn dry_run(output_path: &Path) -> Result<Self, Error> {
+        let path = output_path.join(FAILURES_FILE);
+        let mut attempts = HashMap::new();
+        let mut pending = HashSet::new();
+        for line in read_failures(&path)? {
+            let Some(url) = line["url"].as_str() else {
+                continue;
+            };
+            if line["resolved_at"].is_string() {
+                pending.remove(url);
+            } else {
+                let count = line["attempts"].as_u64().unwrap_or(1) as u32;
+                attempts.insert(url.to_string(), count);
+                pending.insert(url.to_string());
+            }
+        }
+        Ok(Self {
+            path,
+            inner: Mutex::new(Inner {
+                log: None,
+                attempts,
+                pending,
+            }),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append a failure of `url` in `stage`.
+    pub fn record(&self, url: &str, stage: Stage, error: &Error) -> Result<Failure, Error> {
+        let mut inner = self.inner.lock().unwrap();
+        let attempts = inner.attempts.entry(url.to_string()).or_default();
+        *attempts += 1;
+        let failure = Failure {
+            url: url.to_string(),
+            stage,
+            error: serde_json::json!(error),
+            attempts: *attempts,
+            failed_at: Utc::now(),
+        };
+        inner.pending.insert(url.to_string());
+        self.append(&mut inner.log, &failure.to_json())?;
+        Ok(failure)
+    }
+
+    /// Mark `url` as fetched successfully, if it failed before.
+    pub fn resolve(&self, url: &str) -> Result<(), Error> {
+        let mut inner = self.inner.lock().unwrap();
+        if !inner.pending.remove(url) {
+            return Ok(());
+        }
+        let json = serde_json::json!({
+            "url": url,
+            "resolved_at": Utc::now().to_rfc3339(),
+        });
+        self.append(&mut inner.log, &json)
+    }
+
+    fn append(&self, log: &mut Option<fs::File>, json: &serde_json::Value) -> Result<(), Error> {
+        let Some(log) = log.as_mut() else {
+            return Ok(());
+        };
+        let mut line = serde_json::to_vec(json).map_err(|error| Error::Serialization {
+            path: self.path.clone(),
+            source: error,
+        })?;
+        line.push(b'\n');
+        // one write per line, so concurrent runs don't interleave lines
+        log.write_all(&line).map_err(|error| Error::IoWrite {
+            path: self.path.clone(),
+            source: error,
+        })
+    }
+}
+
+/// Read the lines of a failures file, skipping lines that don't parse.
+///
+/// A missing file has no failures.
+pub fn read_failures(path: &Path) -> Result<Vec<serde_json::Value>, Error> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let mut lines = Vec::new();
+    for line in read_lines(path)? {
+        match serde_json::from_str(&line) {
+            Ok(line) => lines.push(line),
+            Err(error) => tracing::warn!("skipping bad line in '{}': {}", path.display(), error),
+        }
+    }
+    Ok(lines)
+}
+
+/// The last failure of every URL in `output_path` that hasn't been resolved,
+/// ordered by URL.
+pub fn pending_failures(output_path: &Path) -> Result<Vec<serde_json::Value>, Error> {
+    let mut pending = BTreeMap::new();
+    for line in read_failures(&output_path.join(FAILURES_FILE))? {
+        let Some(url) = line["url"].as_str().map(str::to_string) else {
+            continue;
+        };
+        if line["resolved_at"].is_string() {
+            pending.remove(&url);
+        } else {
+            pending.insert(url, line);
+        }
+    }
+    Ok(pending.into_values().collect())
+}
@@ -0,0 +1,210 @@
+//! Helpers for reading and writing files in the output directory.
+
+use std::{
+    fs,
+    io::{self, BufRead, Read, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use sha2::{Digest, Sha256};
+
+use crate::Error;
+
+/// All files below `dir` whose name ends with `suffix`, sorted.
+pub(crate) fn files_with_suffix(dir: &Path, suffix: &str) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path.to_string_lossy().ends_with(suffix) {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+pub(crate) fn read_gzip(path: &Path) -> Result<String, Error> {
+    let file = fs::File::open(path).map_err(|error| Error::IoRead {
+        path: path.to_path_buf(),
+        source: error,
+    })?;
+    let mut html = String::new();
+    flate2::read::GzDecoder::new(file)
+        .read_to_string(&mut html)
+        .map_err(|error| Error::IoRead {
+            path: path.to_path_buf(),
+            source: error,
+        })?;
+    Ok(html)
+}
+
+pub(crate) fn read_json(path: &Path) -> Result<serde_json::Value, Error> {
+    let file = fs::File::open(path).map_err(|error| Error::IoRead {
+        path: path.to_path_buf(),
+        source: error,
+    })?;
+    serde_json::from_reader(std::io::BufReader::new(file)).map_err(|error| Error::ParseJson {
+        path: path.to_path_buf(),
+        source: error,
+    })
+}
+
+pub(crate) fn write_json(path: &Path, json_data: &serde_json::Value) -> Result<(), Error> {
+    let mut file = AtomicFile::create(path)?;
+    serde_json::to_writer(&mut file, json_data).map_err(|error| Error::Serialization {
+        path: path.to_path_buf(),
+        source: error,
+    })?;
+    file.commit()
+}
+
+/// Write `data` to `path` through an [`AtomicFile`].
+pub(crate) fn write_atomic(path: &Path, data: &[u8]) -> Result<(), Error> {
+    let mut file = AtomicFile::create(path)?;
+    file.write_all(data).map_err(|error| Error::IoWrite {
+        path: path.to_path_buf(),
+        source: error,
+    })?;
+    file.commit()
+}
+
+/// A unique temporary path in the same directory as `path`, so it can be renamed to `path`.
+pub(crate) fn tmp_path(path: &Path) -> PathBuf {
+    static TMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    path.with_file_name(format!(
+        ".{}.{}-{}.tmp",
+        file_name,
+        std::process::id(),
+        TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+/// A file that is written to a temporary path and renamed to its real path
+/// by [`AtomicFile::commit`], so the real path only ever holds a complete
+/// file. The temporary file is removed if the `AtomicFile` is dropped
+/// without being committed.
+pub(crate) struct AtomicFile {
+    path: PathBuf,
+    tmp: PathBuf,
+    writer: Option<io::BufWriter<fs::File>>,
+}
+
+impl AtomicFile {
+    pub(crate) fn create(path: &Path) -> Result<Self, Error> {
+        let tmp = tmp_path(path);
+        let file = fs::File::create(&tmp).map_err(|error| Error::IoCreate {
+            path: path.to_path_buf(),
+            source: error,
+        })?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            tmp,
+            writer: Some(io::BufWriter::new(file)),
+        })
+    }
+
+    /// Flush and fsync the file and move it into place.
+    pub(crate) fn commit(mut self) -> Result<(), Error> {
+        let write_error = |error| Error::IoWrite {
+            path: self.path.clone(),
+            source: error,
+        };
+        let writer = self.writer.take().expect("not committed");
+        let file = writer
+            .into_inner()
+            .map_err(|error| write_error(error.into_error()))?;
+        file.sync_all().map_err(write_error)?;
+        drop(file);
+        if let Err(error) = fs::rename(&self.tmp, &self.path) {
+            let _ = fs::remove_file(&self.tmp);
+            return Err(write_error(error));
+        }
+        sync_parent(&self.path);
+        Ok(())
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.as_mut().expect("not committed").write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.as_mut().expect("not committed").flush()
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if self.writer.take().is_some() {
+            if let Err(error) = fs::remove_file(&self.tmp) {
+                tracing::warn!("failed removing '{}': {}", self.tmp.display(), error);
+            }
+        }
+    }
+}
+
+/// Make a rename in the parent directory of `path` durable, where supported.
+pub(crate) fn sync_parent(path: &Path) {
+    #[cfg(unix)]
+    if let Some(parent) = path.parent() {
+        if let Err(error) = fs::File::open(parent).and_then(|dir| dir.sync_all()) {
+            tracing::debug!("failed syncing '{}': {}", parent.display(), error);
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+}
+
+pub(crate) fn read_lines(path: &Path) -> Result<Vec<String>, Error> {
+    let file = fs::File::open(path).map_err(|error| Error::IoRead {
+        path: path.to_path_buf(),
+        source: error,
+    })?;
+    std::io::BufReader::new(file)
+        .lines()
+        .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()))
+        .collect::<Result<_, _>>()
+        .map_err(|error| Error::IoRead {
+            path: path.to_path_buf(),
+            source: error,
+        })
+}
+
+/// SHA-256 (hex) and size of a file.
+pub(crate) fn sha256_file(path: &Path) -> Result<(String, u64), Error> {
+    let read_error = |error| Error::IoRead {
+        path: path.to_path_buf(),
+        source: error,
+    };
+    let mut file = fs::File::open(path).map_err(read_error)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut size = 0;
+    loop {
+        let n = file.read(&mut buf).map_err(read_error)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        size += n as u64;
+    }
+    Ok((hex(&hasher.finalize()), size))
+}
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
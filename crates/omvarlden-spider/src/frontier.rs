@@ -0,0 +1,368 @@
+//! Fetching the most valuable URLs first.
+//!
+//! The crawl queue is internal to `webcrawler`, which fetches the URLs
+//! returned by `scrape` in about the order they are returned. The [`Frontier`]
+//! sits in front of it: URLs are scored by a [`PriorityFn`], returned highest
+//! score first, and URLs scoring below [`DEFER_BELOW`] (podcast audio by
+//! default) are held back until the crawl runs low on other work, that is when
+//! fewer fetches than the batch size are running.
+//!
+//! The URLs held back, and those released but not fetched yet, are kept in
+//! `crawl_frontier.txt` in the output directory, rewritten whenever they
+//! change. They can't be found again by a later crawl, since the pages linking
+//! to them are visited in its saved state, so a crawl that doesn't start with
+//! them (like `--retry-failed`) keeps them in the file for the next one.
+
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{BTreeSet, BinaryHeap, HashSet},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use reqwest::Url;
+
+use crate::files::{read_lines, write_atomic};
+use crate::Error;
+
+pub const FRONTIER_FILE: &str = "crawl_frontier.txt";
+
+/// Scores a URL, higher scores are fetched first.
+pub type PriorityFn = fn(&Url) -> u32;
+
+/// Score of article pages.
+pub const ARTICLE: u32 = 300;
+/// Score of pages listing articles or episodes.
+pub const LISTING: u32 = 200;
+/// Score of podcast audio.
+pub const AUDIO: u32 = 100;
+/// URLs scoring below this are held back.
+pub const DEFER_BELOW: u32 = LISTING;
+
+#[derive(Debug, PartialEq, Eq)]
+struct Entry {
+    score: u32,
+    /// Earlier URLs first among equal scores.
+    order: Reverse<u64>,
+    url: String,
+}
+
+impl Ord for Entry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.score, self.order).cmp(&(other.score, other.order))
+    }
+}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[derive(Debug)]
+pub struct Frontier {
+    /// `None` returns URLs as they are found.
+    priority: Option<PriorityFn>,
+    batch: usize,
+    /// `output_path/crawl_frontier.txt`, `None` in dry runs.
+    path: Option<PathBuf>,
+    inner: Mutex<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    queue: BinaryHeap<Entry>,
+    seen: HashSet<String>,
+    next_order: u64,
+    active: usize,
+    /// URLs held back or saved by an earlier crawl that haven't been fetched.
+    unfetched: BTreeSet<String>,
+}
+
+impl Frontier {
+    /// A frontier scoring URLs with `priority`, releasing held back URLs
+    /// `batch` at a time, which should be the crawling concurrency. The URLs
+    /// saved in `output_path` by an earlier crawl are kept until fetched.
+    pub fn open(
+        output_path: &Path,
+        priority: Option<PriorityFn>,
+        batch: usize,
+    ) -> Result<Self, Error> {
+        let mut frontier = Self::dry_run(output_path, priority, batch)?;
+        frontier.path = Some(output_path.join(FRONTIER_FILE));
+        Ok(frontier)
+    }
+
+    /// Like [`Frontier::open`], without ever writing the file.
+    pub fn dry_run(
+        output_path: &Path,
+        priority: Option<PriorityFn>,
+        batch: usize,
+    ) -> Result<Self, Error> {
+        let unfetched = load(output_path)?.into_iter().collect();
+        Ok(Self {
+            priority,
+            batch: batch.max(1),
+            path: None,
+            inner: Mutex::new(Inner {
+                unfetched,
+                ..Inner::default()
+            }),
+        })
+    }
+
+    /// The URLs saved by an earlier crawl that haven't been fetched yet.
+    pub fn saved_urls(&self) -> Vec<String> {
+        self.inner
+            .lock()
+            .unwrap()
+            .unfetched
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    /// Whether `url` is seen for the first time, as start URL or link. Links
+    /// seen before are queued or fetched already, so only new ones should be
+    /// passed to [`Frontier::end`].
+    pub fn see(&self, url: &str) -> bool {
+        self.inner.lock().unwrap().seen.insert(url.to_string())
+    }
+
+    /// Count a fetch as running, call before fetching.
+    pub fn begin(&self) {
+        self.inner.lock().unwrap().active += 1;
+    }
+
+    /// Add the URLs found by fetching `url` and return the URLs to crawl
+    /// next, in order.
+    pub fn end(&self, url: &str, found: Vec<String>) -> Vec<String> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.active = inner.active.saturating_sub(1);
+        let mut changed = inner.unfetched.remove(url);
+        let Some(priority) = self.priority else {
+            if changed {
+                self.write(&inner);
+            }
+            return found;
+        };
+        for url in found {
+            changed |= inner.push(url, priority);
+        }
+        let mut next = Vec::new();
+        while inner
+            .queue
+            .peek()
+            .is_some_and(|entry| entry.score >= DEFER_BELOW)
+        {
+            next.push(inner.queue.pop().unwrap().url);
+        }
+        if next.is_empty() {
+            next = self.release(&mut inner);
+        }
+        if changed {
+            self.write(&inner);
+        }
+        next
+    }
+
+    /// Count fetching `url` as failed and return the held back URLs to crawl
+    /// next, if the crawl is running low on work. The failure is in the
+    /// failures file, so `url` isn't kept here.
+    pub fn end_failed(&self, url: &str) -> Vec<String> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.active = inner.active.saturating_sub(1);
+        if inner.unfetched.remove(url) {
+            self.write(&inner);
+        }
+        self.release(&mut inner)
+    }
+
+    /// Held back URLs to fill up the batch, with few fetches left running the
+    /// crawler is running low on urls.
+    fn release(&self, inner: &mut Inner) -> Vec<String> {
+        let mut next = Vec::new();
+        for _ in inner.active..self.batch {
+            match inner.queue.pop() {
+                // kept in the file until fetched
+                Some(entry) => next.push(entry.url),
+                None => break,
+            }
+        }
+        next
+    }
+
+    /// The number of URLs held back or not fetched yet.
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().unfetched.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Write the URLs not fetched yet to `output_path/crawl_frontier.txt`, or
+    /// remove the file if there are none.
+    pub fn save(&self) -> Result<(), Error> {
+        let inner = self.inner.lock().unwrap();
+        self.try_write(&inner)
+    }
+
+    /// Save after a change, a failure only costs the URLs if the crawl stops
+    /// before the next save.
+    fn write(&self, inner: &Inner) {
+        if let Err(error) = self.try_write(inner) {
+            tracing::warn!("failed saving the frontier: {:#}", error);
+        }
+    }
+
+    fn try_write(&self, inner: &Inner) -> Result<(), Error> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if inner.unfetched.is_empty() {
+            if path.exists() {
+                std::fs::remove_file(path).map_err(|error| Error::IoWrite {
+                    path: path.clone(),
+                    source: error,
+                })?;
+            }
+            return Ok(());
+        }
+        // held back urls in the order they would be released, then the rest
+        let mut entries: Vec<&Entry> = inner.queue.iter().collect();
+        entries.sort_by(|a, b| b.cmp(a));
+        let queued: HashSet<&str> = entries.iter().map(|entry| entry.url.as_str()).collect();
+        let mut data = String::new();
+        let urls = entries.iter().map(|entry| &entry.url).chain(
+            inner
+                .unfetched
+                .iter()
+                .filter(|url| !queued.contains(url.as_str())),
+        );
+        for url in urls {
+            data.push_str(url);
+            data.push('\n');
+        }
+        tracing::debug!(
+            "saving {} urls not crawled yet to '{}'",
+            inner.unfetched.len(),
+            path.display()
+        );
+        write_atomic(path, data.as_bytes())
+    }
+}
+
+impl Inner {
+    /// Queue `url`, whether the urls not fetched changed.
+    fn push(&mut self, url: String, priority: PriorityFn) -> bool {
+        let score = Url::parse(&url).map_or(LISTING, |parsed| priority(&parsed));
+        let deferred = score < DEFER_BELOW && self.unfetched.insert(url.clone());
+        self.next_order += 1;
+        self.queue.push(Entry {
+            score,
+            order: Reverse(self.next_order),
+            url,
+        });
+        deferred
+    }
+}
+
+/// The URLs saved by a [`Frontier`] in `output_path`.
+pub fn load(output_path: &Path) -> Result<Vec<String>, Error> {
+    let path = output_path.join(FRONTIER_FILE);
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    read_lines(&path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn priority(url: &Url) -> u32 {
+        if url.path().ends_with(".mp3") {
+            AUDIO
+        } else {
+            ARTICLE
+        }
+    }
+
+    fn urls(paths: &[&str]) -> Vec<String> {
+        paths
+            .iter()
+            .map(|path| format!("https://www.omvarlden.se{}", path))
+            .collect()
+    }
+
+    #[test]
+    fn releases_held_back_urls_when_running_low() {
+        let output = tempfile::tempdir().unwrap();
+        let frontier = Frontier::open(output.path(), Some(priority), 2).unwrap();
+        let start = urls(&["/"]).remove(0);
+        frontier.begin();
+        frontier.begin();
+        let found = urls(&["/a.mp3", "/b.mp3", "/c.mp3", "/artikel/"]);
+        assert_eq!(frontier.end(&start, found.clone()), urls(&["/artikel/"]));
+        assert_eq!(
+            load(output.path()).unwrap(),
+            urls(&["/a.mp3", "/b.mp3", "/c.mp3"])
+        );
+
+        // one fetch still running, one slot to fill
+        frontier.begin();
+        assert_eq!(frontier.end(&found[3], Vec::new()), urls(&["/a.mp3"]));
+        frontier.begin();
+        assert_eq!(frontier.end(&found[0], Vec::new()), urls(&["/b.mp3"]));
+        assert_eq!(frontier.len(), 2);
+    }
+
+    #[test]
+    fn releases_held_back_urls_when_the_last_fetch_fails() {
+        let output = tempfile::tempdir().unwrap();
+        let frontier = Frontier::open(output.path(), Some(priority), 2).unwrap();
+        let start = urls(&["/"]).remove(0);
+        frontier.begin();
+        let found = urls(&["/a.mp3", "/b.mp3", "/c.mp3", "/artikel/"]);
+        assert_eq!(frontier.end(&start, found.clone()), urls(&["/artikel/"]));
+
+        frontier.begin();
+        assert_eq!(frontier.end_failed(&found[3]), urls(&["/a.mp3", "/b.mp3"]));
+        // kept until fetched
+        assert_eq!(frontier.len(), 3);
+        frontier.begin();
+        frontier.begin();
+        assert_eq!(frontier.end_failed(&found[0]), urls(&["/c.mp3"]));
+        frontier.begin();
+        assert!(frontier.end_failed(&found[2]).is_empty());
+        assert!(frontier.end(&found[1], Vec::new()).is_empty());
+        assert!(frontier.is_empty());
+        assert!(!output.path().join(FRONTIER_FILE).exists());
+    }
+
+    #[test]
+    fn sees_urls_once() {
+        let output = tempfile::tempdir().unwrap();
+        let frontier = Frontier::dry_run(output.path(), None, 2).unwrap();
+        let found = urls(&["/", "/artikel/", "/"]);
+        let new: Vec<&String> = found.iter().filter(|url| frontier.see(url)).collect();
+        assert_eq!(new, vec![&found[0], &found[1]]);
+        assert!(!frontier.see(&found[1]));
+    }
+
+    #[test]
+    fn keeps_saved_urls_until_fetched() {
+        let output = tempfile::tempdir().unwrap();
+        std::fs::write(
+            output.path().join(FRONTIER_FILE),
+            urls(&["/a.mp3", "/b.mp3"]).join("\n"),
+        )
+        .unwrap();
+        let frontier = Frontier::open(output.path(), Some(priority), 2).unwrap();
+        assert_eq!(frontier.saved_urls(), urls(&["/a.mp3", "/b.mp3"]));
+        frontier.begin();
+        frontier.end(&urls(&["/a.mp3"])[0], Vec::new());
+        assert_eq!(load(output.path()).unwrap(), urls(&["/b.mp3"]));
+    }
+}
@@ -0,0 +1,256 @@
+//! Running the spider on a single page, for debugging extraction.
+//!
+//! [`inspect_url`] fetches a URL, [`inspect_file`] reads one from disk, and
+//! both run the extraction of `scrape` on it and work out what `process` would
+//! store, without writing anything or touching the crawl state.
+
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use chrono::Utc;
+use reqwest::Url;
+
+use crate::archive::Archive;
+use crate::article::Article;
+use crate::audio::AudioMetadata;
+use crate::date;
+use crate::files::read_gzip;
+use crate::item::{FetchInfo, Item};
+use crate::omvarlden::{extract, http_client, page_key, podd_key, podd_meta_key};
+use crate::storage::StorageKind;
+use crate::Error;
+
+#[derive(Debug, Clone)]
+pub struct InspectOptions {
+    pub user_agent: Option<String>,
+    /// The output directory the paths are computed for.
+    pub output_path: PathBuf,
+    pub storage: StorageKind,
+}
+
+impl Default for InspectOptions {
+    fn default() -> Self {
+        Self {
+            user_agent: None,
+            output_path: "./output".into(),
+            storage: StorageKind::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Inspection {
+    pub fetch: FetchInfo,
+    pub bytes: usize,
+    /// The URLs `scrape` would return.
+    pub links: Vec<String>,
+    pub items: Vec<InspectedItem>,
+}
+
+/// An item `scrape` would return.
+#[derive(Debug, Clone)]
+pub struct InspectedItem {
+    pub kind: &'static str,
+    /// The fields extracted from the item: article fields, episode metadata
+    /// or audio metadata.
+    pub fields: serde_json::Value,
+    /// The key `process` would store the item under, `None` if it is skipped.
+    pub key: Option<String>,
+    /// Where `process` would write the item.
+    pub output: Option<String>,
+}
+
+impl InspectedItem {
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "kind": self.kind,
+            "key": self.key,
+            "output": self.output,
+            "fields": self.fields,
+        })
+    }
+}
+
+impl Inspection {
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "fetch": self.fetch.to_json(),
+            "bytes": self.bytes,
+            "links": self.links,
+            "items": self.items.iter().map(InspectedItem::to_json).collect::<Vec<_>>(),
+        })
+    }
+}
+
+impl fmt::Display for Inspection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "url:          {}", self.fetch.url)?;
+        if self.fetch.final_url != self.fetch.url {
+            writeln!(f, "final url:    {}", self.fetch.final_url)?;
+        }
+        writeln!(f, "status:       {}", self.fetch.status)?;
+        writeln!(
+            f,
+            "content type: {}",
+            self.fetch.content_type.as_deref().unwrap_or("-")
+        )?;
+        writeln!(f, "bytes:        {}", self.bytes)?;
+        writeln!(f, "links:        {}", self.links.len())?;
+        for link in &self.links {
+            writeln!(f, "  {}", link)?;
+        }
+        writeln!(f, "items:        {}", self.items.len())?;
+        for item in &self.items {
+            writeln!(f, "  {}", item.kind)?;
+            match (&item.key, &item.output) {
+                (Some(key), Some(output)) => {
+                    writeln!(f, "    key:    {}", key)?;
+                    writeln!(f, "    output: {}", output)?;
+                }
+                (Some(key), None) => writeln!(f, "    key:    {}", key)?,
+                (None, _) => writeln!(f, "    skipped, not stored")?,
+            }
+            if let Some(fields) = item.fields.as_object() {
+                for (name, value) in fields {
+                    writeln!(f, "    {:<14} {}", format!("{}:", name), field_text(value))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A field value on one line, shortened if long.
+fn field_text(value: &serde_json::Value) -> String {
+    const MAX_CHARS: usize = 100;
+    let text = match value {
+        serde_json::Value::String(text) => text.replace('\n', " "),
+        value => value.to_string(),
+    };
+    if text.chars().count() > MAX_CHARS {
+        let short: String = text.chars().take(MAX_CHARS).collect();
+        format!("{}… ({} chars)", short, text.chars().count())
+    } else {
+        text
+    }
+}
+
+/// Fetch `url` and inspect it.
+pub async fn inspect_url(url: &str, options: &InspectOptions) -> Result<Inspection, Error> {
+    let http_client = http_client(options.user_agent.as_deref())?;
+    let response = http_client
+        .get(url)
+        .send()
+        .await
+        .map_err(|error| Error::Fetch {
+            url: url.to_string(),
+            source: error,
+        })?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(Error::HttpStatus {
+            url: url.to_string(),
+            status,
+        });
+    }
+    let fetch = FetchInfo::from_response(url, &response);
+    let body = response.bytes().await.map_err(|error| Error::Fetch {
+        url: url.to_string(),
+        source: error,
+    })?;
+    Ok(inspect(url, fetch, body.to_vec(), options))
+}
+
+/// Inspect the page in `path`, as if fetched from `url`. Gzipped pages, like
+/// the `.html.gz` files in an output directory, are decompressed.
+pub fn inspect_file(path: &Path, url: &str, options: &InspectOptions) -> Result<Inspection, Error> {
+    let body = if path.extension().is_some_and(|extension| extension == "gz") {
+        read_gzip(path)?.into_bytes()
+    } else {
+        std::fs::read(path).map_err(|error| Error::IoRead {
+            path: path.to_path_buf(),
+            source: error,
+        })?
+    };
+    let content_type = if path
+        .extension()
+        .is_some_and(|extension| extension == "json")
+    {
+        "application/json"
+    } else {
+        "text/html"
+    };
+    let fetch = FetchInfo {
+        url: url.to_string(),
+        final_url: url.to_string(),
+        status: 200,
+        content_type: Some(content_type.to_string()),
+        fetched_at: Utc::now(),
+    };
+    Ok(inspect(url, fetch, body, options))
+}
+
+fn inspect(url: &str, fetch: FetchInfo, body: Vec<u8>, options: &InspectOptions) -> Inspection {
+    let bytes = body.len();
+    let archive = Archive::new();
+    let (items, links) = extract(&archive, url, fetch.clone(), body);
+    let parsed_url = Url::parse(url).ok();
+    let items = items
+        .into_iter()
+        .map(|item| {
+            let kind = item.kind();
+            let (fields, key) = match item {
+                Item::Html { html, .. } => (
+                    Article::from_html(&html, fetch.fetched_at).to_json(),
+                    parsed_url.as_ref().and_then(|url| page_key(&archive, url)),
+                ),
+                Item::PoddMeta {
+                    meta_url,
+                    title,
+                    release_date,
+                    description,
+                    mp3_url,
+                    ..
+                } => (
+                    serde_json::json!({
+                        "title": title,
+                        "meta_url": meta_url,
+                        "release_date": date::parse_date(release_date.trim(), fetch.fetched_at)
+                            .ok()
+                            .map(|date| date.iso()),
+                        "release_date_raw": release_date.trim(),
+                        "description": description,
+                        "mp3_url": mp3_url,
+                    }),
+                    Url::parse(&meta_url)
+                        .ok()
+                        .map(|meta_url| podd_meta_key(&meta_url)),
+                ),
+                Item::Podd { data, .. } => (
+                    match AudioMetadata::from_bytes(&data) {
+                        Ok(audio) => audio.to_json(),
+                        Err(error) => serde_json::json!({ "error": error.to_string() }),
+                    },
+                    parsed_url.as_ref().map(podd_key),
+                ),
+            };
+            let output = key
+                .as_deref()
+                .map(|key| options.storage.location(&options.output_path, key));
+            InspectedItem {
+                kind,
+                fields,
+                key,
+                output,
+            }
+        })
+        .collect();
+    Inspection {
+        fetch,
+        bytes,
+        links,
+        items,
+    }
+}
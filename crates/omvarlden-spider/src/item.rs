@@ -1,12 +1,76 @@
+use chrono::{DateTime, Utc};
+
 #[derive(Debug, Clone)]
 pub enum Item {
-    Html(String),
+    Html {
+        fetch: FetchInfo,
+        html: String,
+    },
     PoddMeta {
+        fetch: FetchInfo,
         meta_url: String,
         title: String,
         release_date: String,
         description: Option<String>,
         mp3_url: String,
     },
-    Podd(Vec<u8>),
+    Podd {
+        fetch: FetchInfo,
+        data: Vec<u8>,
+    },
+}
+
+impl Item {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Html { .. } => "html",
+            Self::PoddMeta { .. } => "podd_meta",
+            Self::Podd { .. } => "podd",
+        }
+    }
+
+    pub fn fetch(&self) -> &FetchInfo {
+        match self {
+            Self::Html { fetch, .. } | Self::PoddMeta { fetch, .. } | Self::Podd { fetch, .. } => {
+                fetch
+            }
+        }
+    }
+}
+
+/// How the content of an item was fetched.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FetchInfo {
+    pub url: String,
+    /// The url after following redirects.
+    pub final_url: String,
+    pub status: u16,
+    pub content_type: Option<String>,
+    pub fetched_at: DateTime<Utc>,
+}
+
+impl FetchInfo {
+    pub fn from_response(url: &str, response: &reqwest::Response) -> Self {
+        Self {
+            url: url.to_string(),
+            final_url: response.url().to_string(),
+            status: response.status().as_u16(),
+            content_type: response
+                .headers()
+                .get(reqwest::header::CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string),
+            fetched_at: Utc::now(),
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "url": self.url,
+            "final_url": self.final_url,
+            "status": self.status,
+            "content_type": self.content_type,
+            "fetched_at": self.fetched_at.to_rfc3339(),
+        })
+    }
 }
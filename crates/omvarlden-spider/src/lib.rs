@@ -1,6 +1,25 @@
+pub mod archive;
+pub mod article;
+pub mod audio;
+pub mod budget;
+pub mod cas;
+pub mod date;
+pub mod episodes;
 mod error;
+pub mod failures;
+mod files;
+pub mod frontier;
+pub mod inspect;
 pub mod item;
+pub mod linking;
+pub mod manifest;
+pub mod metrics;
 pub mod omvarlden;
+pub mod paths;
+pub mod shutdown;
+pub mod stats;
+pub mod storage;
+pub mod verify;
 
 pub use error::Error;
 pub static APP_USER_AGENT: &str = concat!(
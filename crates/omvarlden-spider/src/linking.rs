@@ -0,0 +1,440 @@
+//! Cross-referencing podcast episodes with their articles on omvarlden.se.
+//!
+//! Episodes (`podd_meta/**/*.json`) are matched against the pages saved under
+//! `poddar/`, first by embedded player or links to the episode, then by title
+//! similarity and release date. Matches are written to the episode JSON under
+//! `articles`, recorded in the crawl manifest so `verify` checks the new
+//! content, and to a `.meta.json` file next to each matched article.
+//!
+//! Relative dates on the pages ("igår") are resolved against the time the page
+//! was fetched, taken from the crawl manifest.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Utc};
+
+use crate::article::Article;
+use crate::date;
+use crate::files::{files_with_suffix, read_gzip, read_json, write_json};
+use crate::manifest::{read_manifest, ContentDigest, CrawlManifest, ManifestOutput, MANIFEST_FILE};
+use crate::omvarlden::OmvarldenSpider;
+use crate::paths;
+use crate::storage::check_tree_layout;
+use crate::Error;
+
+/// Minimum title similarity for a match when the dates agree.
+const TITLE_SIMILARITY: f64 = 0.5;
+/// Minimum title similarity for a match when a date is missing.
+const TITLE_SIMILARITY_WITHOUT_DATE: f64 = 0.8;
+/// Maximum number of days between episode release and article publication.
+const MAX_DAYS_APART: i64 = 3;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LinkReport {
+    pub episodes: usize,
+    pub articles: usize,
+    pub links: usize,
+    /// Episode and article files that couldn't be read, and were skipped.
+    pub unreadable: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Method {
+    PlayerUrl,
+    Title,
+}
+
+impl Method {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::PlayerUrl => "player_url",
+            Self::Title => "title",
+        }
+    }
+}
+
+struct Episode {
+    path: PathBuf,
+    json: serde_json::Value,
+    fetched_at: DateTime<Utc>,
+}
+
+struct SavedArticle {
+    path: PathBuf,
+    url: String,
+    article: Article,
+}
+
+struct Link {
+    episode: usize,
+    article: usize,
+    method: Method,
+    score: f64,
+}
+
+pub fn link_episodes(output_path: &Path) -> Result<LinkReport, Error> {
+    check_tree_layout(output_path, "linking")?;
+    let fetch_times = FetchTimes::read(output_path);
+    let mut unreadable = 0;
+    let mut skip = |error: Error| {
+        tracing::warn!("skipping: {:#}", error);
+        unreadable += 1;
+    };
+    let mut episodes = Vec::new();
+    for path in files_with_suffix(&output_path.join("podd_meta"), ".json") {
+        let json = match read_json(&path) {
+            Ok(json) => json,
+            Err(error) => {
+                skip(error);
+                continue;
+            }
+        };
+        let fetched_at = fetch_times.get(&path);
+        episodes.push(Episode {
+            path,
+            json,
+            fetched_at,
+        });
+    }
+    let mut articles = Vec::new();
+    for path in files_with_suffix(&output_path.join("poddar"), ".html.gz") {
+        let html = match read_gzip(&path) {
+            Ok(html) => html,
+            Err(error) => {
+                skip(error);
+                continue;
+            }
+        };
+        let url = article_url(output_path, &path);
+        let article = Article::from_html(&html, fetch_times.get(&path));
+        articles.push(SavedArticle { path, url, article });
+    }
+    tracing::info!(
+        "linking {} episodes with {} articles",
+        episodes.len(),
+        articles.len()
+    );
+
+    let mut links = Vec::new();
+    for (e, episode) in episodes.iter().enumerate() {
+        let mut best_title_match: Option<Link> = None;
+        let mut found_player = false;
+        for (a, article) in articles.iter().enumerate() {
+            if embeds_episode(&article.article, &episode.json) {
+                found_player = true;
+                links.push(Link {
+                    episode: e,
+                    article: a,
+                    method: Method::PlayerUrl,
+                    score: 1.0,
+                });
+            } else if let Some(score) = title_score(&article.article, episode) {
+                if best_title_match.as_ref().map_or(true, |l| score > l.score) {
+                    best_title_match = Some(Link {
+                        episode: e,
+                        article: a,
+                        method: Method::Title,
+                        score,
+                    });
+                }
+            }
+        }
+        if !found_player {
+            links.extend(best_title_match);
+        }
+    }
+
+    let relative = |path: &Path| {
+        path.strip_prefix(output_path)
+            .unwrap_or(path)
+            .display()
+            .to_string()
+    };
+    let manifest = CrawlManifest::open(output_path)?;
+    for (e, episode) in episodes.iter().enumerate() {
+        let mut json = episode.json.clone();
+        json["articles"] = links
+            .iter()
+            .filter(|link| link.episode == e)
+            .map(|link| {
+                let article = &articles[link.article];
+                serde_json::json!({
+                    "url": article.url,
+                    "path": relative(&article.path),
+                    "title": article.article.title,
+                    "method": link.method.as_str(),
+                    "score": link.score,
+                })
+            })
+            .collect();
+        if json == episode.json {
+            continue;
+        }
+        write_json(&episode.path, &json)?;
+        let key = relative(&episode.path).replace('\\', "/");
+        let output = ManifestOutput::new(
+            &key,
+            Some(episode.path.clone()),
+            &ContentDigest::of_document(&json),
+        );
+        let url = episode.json["meta_url"].as_str().unwrap_or_default();
+        manifest.record_update(url, &[output])?;
+    }
+    let mut linked_articles = HashSet::new();
+    for (a, article) in articles.iter().enumerate() {
+        let article_links: Vec<_> = links.iter().filter(|link| link.article == a).collect();
+        if article_links.is_empty() {
+            continue;
+        }
+        linked_articles.insert(a);
+        let mut json = article.article.to_json();
+        json["url"] = article.url.clone().into();
+        json["episodes"] = article_links
+            .iter()
+            .map(|link| {
+                let episode = &episodes[link.episode];
+                serde_json::json!({
+                    "path": relative(&episode.path),
+                    "title": episode.json["title"],
+                    "meta_url": episode.json["meta_url"],
+                    "mp3_url": episode.json["mp3_url"],
+                    "method": link.method.as_str(),
+                    "score": link.score,
+                })
+            })
+            .collect();
+        write_json(&article_meta_path(&article.path), &json)?;
+    }
+
+    Ok(LinkReport {
+        episodes: episodes.len(),
+        articles: linked_articles.len(),
+        links: links.len(),
+        unreadable,
+    })
+}
+
+/// The metadata file stored next to an article, `a/b.html.gz` -> `a/b.meta.json`.
+pub fn article_meta_path(article_path: &Path) -> PathBuf {
+    let file_name = article_path
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    let stem = file_name.strip_suffix(".html.gz").unwrap_or(&file_name);
+    article_path.with_file_name(format!("{}.meta.json", stem))
+}
+
+/// When the files in an output directory were fetched.
+struct FetchTimes<'a> {
+    output_path: &'a Path,
+    /// By key, from the crawl manifest.
+    by_key: HashMap<String, DateTime<Utc>>,
+}
+
+impl<'a> FetchTimes<'a> {
+    fn read(output_path: &'a Path) -> Self {
+        let mut by_key = HashMap::new();
+        let manifest_path = output_path.join(MANIFEST_FILE);
+        let entries = if manifest_path.exists() {
+            read_manifest(&manifest_path).unwrap_or_else(|error| {
+                tracing::warn!("no fetch times, relative dates may be off: {:#}", error);
+                Vec::new()
+            })
+        } else {
+            Vec::new()
+        };
+        for entry in entries {
+            let Some(fetched_at) = entry["fetched_at"]
+                .as_str()
+                .and_then(|fetched_at| DateTime::parse_from_rfc3339(fetched_at).ok())
+            else {
+                continue;
+            };
+            for output in entry["outputs"].as_array().into_iter().flatten() {
+                if let Some(key) = output["key"].as_str() {
+                    by_key.insert(key.to_string(), fetched_at.with_timezone(&Utc));
+                }
+            }
+        }
+        Self {
+            output_path,
+            by_key,
+        }
+    }
+
+    /// When the file at `path` was fetched, going by when it was written if it
+    /// isn't in the manifest.
+    fn get(&self, path: &Path) -> DateTime<Utc> {
+        let relative = path.strip_prefix(self.output_path).unwrap_or(path);
+        let key = relative.to_string_lossy().replace('\\', "/");
+        self.by_key.get(&key).copied().unwrap_or_else(|| {
+            path.metadata()
+                .and_then(|metadata| metadata.modified())
+                .map(DateTime::from)
+                .unwrap_or_else(|_| Utc::now())
+        })
+    }
+}
+
+fn article_url(output_path: &Path, path: &Path) -> String {
+    let relative = path.strip_prefix(output_path).unwrap_or(path);
+    let key = relative.to_string_lossy().replace('\\', "/");
+    match paths::page_url(&key) {
+        Some(url) => url.to_string(),
+        None => format!("{}/{}", OmvarldenSpider::BASE_URL, key),
+    }
+}
+
+fn embeds_episode(article: &Article, episode: &serde_json::Value) -> bool {
+    let mp3_file = episode["mp3_url"]
+        .as_str()
+        .and_then(|url| url.split('?').next())
+        .and_then(|url| url.rsplit('/').next())
+        .filter(|name| !name.is_empty());
+    let meta_url = episode["meta_url"]
+        .as_str()
+        .map(|url| url.trim_end_matches('/'))
+        .filter(|url| !url.is_empty());
+    article.podcast_urls.iter().any(|url| {
+        meta_url.is_some_and(|meta_url| url.trim_end_matches('/') == meta_url)
+            || mp3_file.is_some_and(|mp3_file| url.contains(mp3_file))
+    })
+}
+
+fn title_score(article: &Article, episode: &Episode) -> Option<f64> {
+    let similarity = similarity(article.title.as_deref()?, episode.json["title"].as_str()?);
+    let article_date = article.published_date.as_ref().map(date::ParsedDate::date);
+    let episode_date = episode.json["release_date"]
+        .as_str()
+        .or_else(|| episode.json["release_date_raw"].as_str())
+        .and_then(|release_date| date::parse_date(release_date, episode.fetched_at).ok())
+        .map(|release_date| release_date.date());
+    match (article_date, episode_date) {
+        (Some(article_date), Some(episode_date)) => {
+            let days_apart = (article_date - episode_date).num_days().abs();
+            (days_apart <= MAX_DAYS_APART && similarity >= TITLE_SIMILARITY).then_some(similarity)
+        }
+        _ => (similarity >= TITLE_SIMILARITY_WITHOUT_DATE).then_some(similarity),
+    }
+}
+
+/// Jaccard similarity of the lowercased words in two titles.
+fn similarity(a: &str, b: &str) -> f64 {
+    let words = |s: &str| -> HashSet<String> {
+        s.split(|c: char| !c.is_alphanumeric())
+            .filter(|w| !w.is_empty())
+            .map(str::to_lowercase)
+            .collect()
+    };
+    let (a, b) = (words(a), words(b));
+    let union = a.union(&b).count();
+    if union == 0 {
+        return 0.0;
+    }
+    a.intersection(&b).count() as f64 / union as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{write::GzEncoder, Compression};
+    use reqwest::Url;
+
+    use super::*;
+    use crate::item::FetchInfo;
+    use crate::manifest::ManifestEntry;
+    use crate::storage::{FsStorage, Storage};
+    use crate::verify::verify;
+
+    fn fetch(url: &str) -> FetchInfo {
+        FetchInfo {
+            url: url.to_string(),
+            final_url: url.to_string(),
+            status: 200,
+            content_type: None,
+            fetched_at: "2024-03-02T10:00:00Z".parse().unwrap(),
+        }
+    }
+
+    /// Store `data` under `key` like a crawl does, recorded in the manifest.
+    fn store(output_path: &Path, kind: &'static str, url: &str, key: &str, data: &[u8]) {
+        let storage = FsStorage::new(output_path);
+        storage.put_blob(key, url, data).unwrap();
+        let mut entry = ManifestEntry::new(kind, fetch(url));
+        entry.outputs.push(ManifestOutput::new(
+            key,
+            storage.local_path(key),
+            &ContentDigest::of(data),
+        ));
+        CrawlManifest::open(output_path)
+            .unwrap()
+            .record(&entry)
+            .unwrap();
+    }
+
+    #[test]
+    fn verifies_after_linking() {
+        let output = tempfile::tempdir().unwrap();
+        let meta_url = format!("{}/poddar/avsnitt-1/", OmvarldenSpider::BASE_URL);
+        let episode = serde_json::json!({
+            "title": "Vattnet tar slut",
+            "meta_url": meta_url,
+            "release_date": "2024-03-01",
+            "mp3_url": "https://traffic.libsyn.com/omvarlden/avsnitt-1.mp3",
+        });
+        store(
+            output.path(),
+            "podd_meta",
+            &meta_url,
+            "podd_meta/poddar/avsnitt-1.json",
+            episode.to_string().as_bytes(),
+        );
+        let page_url = format!("{}/poddar/vattnet-tar-slut/", OmvarldenSpider::BASE_URL);
+        let html = r#"<html><head><meta property="og:title" content="Vattnet tar slut">
+            </head><body><article><p>Ett avsnitt.</p>
+            <iframe src="https://html5-player.libsyn.com/embed/avsnitt-1.mp3"></iframe>
+            </article></body></html>"#;
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(html.as_bytes()).unwrap();
+        store(
+            output.path(),
+            "html",
+            &page_url,
+            &paths::page_key(&Url::parse(&page_url).unwrap()),
+            &encoder.finish().unwrap(),
+        );
+        assert!(verify(output.path()).unwrap().problems.is_empty());
+
+        let report = link_episodes(output.path()).unwrap();
+        assert_eq!(report.links, 1);
+        let episode = read_json(&output.path().join("podd_meta/poddar/avsnitt-1.json")).unwrap();
+        assert_eq!(episode["articles"][0]["url"], page_url);
+        assert_eq!(verify(output.path()).unwrap().problems, Vec::new());
+
+        // linking again changes nothing
+        let manifest = read_manifest(&output.path().join(MANIFEST_FILE)).unwrap();
+        link_episodes(output.path()).unwrap();
+        assert_eq!(
+            read_manifest(&output.path().join(MANIFEST_FILE)).unwrap(),
+            manifest
+        );
+        assert_eq!(verify(output.path()).unwrap().problems, Vec::new());
+    }
+
+    #[test]
+    fn rejects_single_file_storages() {
+        let output = tempfile::tempdir().unwrap();
+        std::fs::write(output.path().join(crate::storage::TAR_FILE), b"").unwrap();
+        assert!(matches!(
+            link_episodes(output.path()),
+            Err(Error::Config {
+                what: "linking",
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,220 @@
+//! A record of every processed item, for documenting the provenance of the corpus.
+//!
+//! `crawl_manifest.jsonl` in the output directory gets one line per item, with
+//! how it was fetched, the size and SHA-256 of the fetched content and what was
+//! stored where. Lines are appended across runs, so a URL fetched again by a
+//! later run appears once per run. Documents rewritten after the crawl, like
+//! episodes linked to their articles, get a line of kind [`UPDATE_KIND`] with
+//! their new size and SHA-256, without fetch fields.
+
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use sha2::{Digest, Sha256};
+
+use crate::files::{hex, read_lines};
+use crate::item::FetchInfo;
+use crate::Error;
+
+pub const MANIFEST_FILE: &str = "crawl_manifest.jsonl";
+
+/// Kind of the entries recording outputs rewritten after being stored.
+pub const UPDATE_KIND: &str = "update";
+
+/// Size and SHA-256 of some content, computed once and shared by everything
+/// recording it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentDigest {
+    pub size: u64,
+    pub sha256: String,
+}
+
+impl ContentDigest {
+    pub fn of(data: &[u8]) -> Self {
+        Self {
+            size: data.len() as u64,
+            sha256: hex(&Sha256::digest(data)),
+        }
+    }
+
+    /// The digest of `document` as stored, storages write documents as
+    /// compact JSON.
+    pub fn of_document(document: &serde_json::Value) -> Self {
+        Self::of(document.to_string().as_bytes())
+    }
+}
+
+/// Something stored for an item.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestOutput {
+    pub key: String,
+    /// Where `key` is stored, for storages writing one file per key.
+    pub path: Option<PathBuf>,
+    /// Size and SHA-256 of the stored bytes. A document updated later is
+    /// recorded again by the entry updating it.
+    pub size: Option<u64>,
+    pub sha256: Option<String>,
+}
+
+impl ManifestOutput {
+    pub fn new(key: &str, path: Option<PathBuf>, digest: &ContentDigest) -> Self {
+        Self {
+            key: key.to_string(),
+            path,
+            size: Some(digest.size),
+            sha256: Some(digest.sha256.clone()),
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "key": self.key,
+            "path": self.path,
+            "size": self.size,
+            "sha256": self.sha256,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestEntry {
+    pub kind: &'static str,
+    pub fetch: FetchInfo,
+    /// Size and SHA-256 of the fetched content, `None` for items taken from a
+    /// page that wasn't stored itself.
+    pub size: Option<u64>,
+    pub sha256: Option<String>,
+    pub outputs: Vec<ManifestOutput>,
+    /// Why nothing was stored, if so.
+    pub skipped: Option<&'static str>,
+}
+
+impl ManifestEntry {
+    pub fn new(kind: &'static str, fetch: FetchInfo) -> Self {
+        Self {
+            kind,
+            fetch,
+            size: None,
+            sha256: None,
+            outputs: Vec::new(),
+            skipped: None,
+        }
+    }
+
+    /// Record the size and checksum of the fetched content.
+    pub fn with_content(mut self, digest: &ContentDigest) -> Self {
+        self.size = Some(digest.size);
+        self.sha256 = Some(digest.sha256.clone());
+        self
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut json = self.fetch.to_json();
+        json["kind"] = self.kind.into();
+        json["size"] = self.size.into();
+        json["sha256"] = self.sha256.clone().into();
+        json["outputs"] = self.outputs.iter().map(ManifestOutput::to_json).collect();
+        json["skipped"] = self.skipped.into();
+        json["processed_at"] = chrono::Utc::now().to_rfc3339().into();
+        json
+    }
+}
+
+pub struct CrawlManifest {
+    output_path: PathBuf,
+    path: PathBuf,
+    /// `None` in dry runs.
+    log: Mutex<Option<fs::File>>,
+}
+
+impl CrawlManifest {
+    /// Open `output_path/crawl_manifest.jsonl` for appending.
+    pub fn open(output_path: &Path) -> Result<Self, Error> {
+        let path = output_path.join(MANIFEST_FILE);
+        let log = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|error| Error::IoCreate {
+                path: path.clone(),
+                source: error,
+            })?;
+        Ok(Self {
+            output_path: output_path.to_path_buf(),
+            path,
+            log: Mutex::new(Some(log)),
+        })
+    }
+
+    /// A manifest for `output_path` that doesn't record anything.
+    pub fn dry_run(output_path: &Path) -> Self {
+        Self {
+            output_path: output_path.to_path_buf(),
+            path: output_path.join(MANIFEST_FILE),
+            log: Mutex::new(None),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append `entry`, with output paths relative to the output directory.
+    pub fn record(&self, entry: &ManifestEntry) -> Result<(), Error> {
+        let json = entry.to_json();
+        self.append(&entry.outputs, json)
+    }
+
+    /// Append an entry for `outputs` of `url` rewritten after being stored.
+    pub fn record_update(&self, url: &str, outputs: &[ManifestOutput]) -> Result<(), Error> {
+        let json = serde_json::json!({
+            "url": url,
+            "kind": UPDATE_KIND,
+            "outputs": outputs.iter().map(ManifestOutput::to_json).collect::<Vec<_>>(),
+            "processed_at": chrono::Utc::now().to_rfc3339(),
+        });
+        self.append(outputs, json)
+    }
+
+    fn append(&self, outputs: &[ManifestOutput], mut json: serde_json::Value) -> Result<(), Error> {
+        for (output, json) in outputs.iter().zip(json["outputs"].as_array_mut().unwrap()) {
+            if let Some(path) = &output.path {
+                json["path"] = path
+                    .strip_prefix(&self.output_path)
+                    .unwrap_or(path)
+                    .to_string_lossy()
+                    .into();
+            }
+        }
+        let mut line = serde_json::to_vec(&json).map_err(|error| Error::Serialization {
+            path: self.path.clone(),
+            source: error,
+        })?;
+        line.push(b'\n');
+        let mut log = self.log.lock().unwrap();
+        let Some(log) = log.as_mut() else {
+            return Ok(());
+        };
+        // one write per line, so concurrent runs don't interleave lines
+        log.write_all(&line).map_err(|error| Error::IoWrite {
+            path: self.path.clone(),
+            source: error,
+        })
+    }
+}
+
+/// Read the entries of a crawl manifest, skipping lines that don't parse.
+pub fn read_manifest(path: &Path) -> Result<Vec<serde_json::Value>, Error> {
+    let mut entries = Vec::new();
+    for line in read_lines(path)? {
+        match serde_json::from_str(&line) {
+            Ok(entry) => entries.push(entry),
+            Err(error) => tracing::warn!("skipping bad line in '{}': {}", path.display(), error),
+        }
+    }
+    Ok(entries)
+}
@@ -0,0 +1,464 @@
+//! Prometheus metrics for following long crawls.
+//!
+//! The metrics are rendered in the Prometheus text format, either served over
+//! HTTP by [`Metrics::serve`] or written periodically to a file for the
+//! node_exporter textfile collector by [`Metrics::write_textfile_every`].
+//!
+//! The crawler's queue is internal to `webcrawler`, so instead of its size the
+//! gauges show how many fetches and items are in flight, and how many distinct
+//! URLs have been seen and fetched per host. Whether a URL was seen before is
+//! up to the caller, which keeps the URLs for the crawl anyway, so the metrics
+//! only count. [`Metrics::snapshot`] gives the same numbers for showing
+//! progress.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io::{self, BufRead, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use reqwest::Url;
+
+use crate::files::write_atomic;
+use crate::Error;
+
+const FETCH_BUCKETS: [f64; 10] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+const PROCESS_BUCKETS: [f64; 9] = [0.001, 0.005, 0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 5.0];
+
+/// Timeout of each read or write of a metrics request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// The most of a metrics request read, the headers aren't used.
+const MAX_REQUEST_BYTES: u64 = 16 * 1024;
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    inner: Mutex<Registry>,
+}
+
+#[derive(Debug, Default)]
+struct Registry {
+    fetches: BTreeMap<(String, u16), u64>,
+    hosts: BTreeMap<String, HostMetrics>,
+    discovered_urls: u64,
+    items: BTreeMap<String, u64>,
+    errors: BTreeMap<String, u64>,
+    fetch_seconds: Histogram,
+    process_seconds: BTreeMap<String, Histogram>,
+    fetches_in_flight: i64,
+    items_in_flight: i64,
+}
+
+/// Progress of the crawl of one host.
+#[derive(Debug, Default, Clone)]
+pub struct HostMetrics {
+    /// Distinct URLs seen, as start URL or link.
+    pub known_urls: u64,
+    /// URLs fetched or being fetched.
+    pub visited_urls: u64,
+    /// Bytes of content downloaded.
+    pub bytes: u64,
+}
+
+/// The current values of the metrics needed to show progress.
+#[derive(Debug, Default, Clone)]
+pub struct MetricsSnapshot {
+    pub hosts: BTreeMap<String, HostMetrics>,
+    pub items: BTreeMap<String, u64>,
+    pub errors: BTreeMap<String, u64>,
+    pub fetches_in_flight: i64,
+    pub items_in_flight: i64,
+}
+
+impl MetricsSnapshot {
+    pub fn known_urls(&self) -> u64 {
+        self.hosts.values().map(|host| host.known_urls).sum()
+    }
+
+    pub fn visited_urls(&self) -> u64 {
+        self.hosts.values().map(|host| host.visited_urls).sum()
+    }
+
+    /// URLs seen but not fetched yet, an estimate of the crawler's queue.
+    pub fn queued_urls(&self) -> u64 {
+        self.known_urls().saturating_sub(self.visited_urls())
+    }
+
+    pub fn items(&self, kind: &str) -> u64 {
+        self.items.get(kind).copied().unwrap_or_default()
+    }
+
+    pub fn error_count(&self) -> u64 {
+        self.errors.values().sum()
+    }
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+    /// Counts per bucket, not cumulative.
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, buckets: &[f64], value: f64) {
+        if self.counts.is_empty() {
+            self.counts = vec![0; buckets.len()];
+        }
+        if let Some(i) = buckets.iter().position(|bound| value <= *bound) {
+            self.counts[i] += 1;
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str, buckets: &[f64]) {
+        let mut cumulative = 0;
+        for (i, bound) in buckets.iter().enumerate() {
+            cumulative += self.counts.get(i).copied().unwrap_or_default();
+            let _ = writeln!(
+                out,
+                "{}_bucket{{{}le=\"{}\"}} {}",
+                name, labels, bound, cumulative
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{}_bucket{{{}le=\"+Inf\"}} {}",
+            name, labels, self.count
+        );
+        let labels = match labels.trim_end_matches(',') {
+            "" => String::new(),
+            labels => format!("{{{}}}", labels),
+        };
+        let _ = writeln!(out, "{}_sum{} {}", name, labels, self.sum);
+        let _ = writeln!(out, "{}_count{} {}", name, labels, self.count);
+    }
+}
+
+/// Decrements an in-flight gauge when dropped.
+pub struct InFlight<'a> {
+    metrics: &'a Metrics,
+    fetch: bool,
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        let mut inner = self.metrics.inner.lock().unwrap();
+        if self.fetch {
+            inner.fetches_in_flight -= 1;
+        } else {
+            inner.items_in_flight -= 1;
+        }
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a fetch of `url` as started until the guard is dropped, and `url`
+    /// as known if it's `new`, like start urls.
+    pub fn start_fetch(&self, url: &str, new: bool) -> InFlight<'_> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.fetches_in_flight += 1;
+        let host = inner.hosts.entry(host(url)).or_default();
+        host.visited_urls += 1;
+        if new {
+            host.known_urls += 1;
+        }
+        drop(inner);
+        InFlight {
+            metrics: self,
+            fetch: true,
+        }
+    }
+
+    pub fn start_item(&self) -> InFlight<'_> {
+        self.inner.lock().unwrap().items_in_flight += 1;
+        InFlight {
+            metrics: self,
+            fetch: false,
+        }
+    }
+
+    pub fn record_fetch(&self, host: &str, status: u16, latency: Duration) {
+        let mut inner = self.inner.lock().unwrap();
+        *inner.fetches.entry((host.to_string(), status)).or_default() += 1;
+        inner
+            .fetch_seconds
+            .observe(&FETCH_BUCKETS, latency.as_secs_f64());
+    }
+
+    pub fn record_bytes(&self, host: &str, bytes: usize) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.hosts.entry(host.to_string()).or_default().bytes += bytes as u64;
+    }
+
+    /// Count `found` links, of which `new_urls` weren't seen before.
+    pub fn record_discovered(&self, found: usize, new_urls: &[String]) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.discovered_urls += found as u64;
+        for url in new_urls {
+            inner.hosts.entry(host(url)).or_default().known_urls += 1;
+        }
+    }
+
+    pub fn record_item(&self, kind: &str, duration: Duration) {
+        let mut inner = self.inner.lock().unwrap();
+        *inner.items.entry(kind.to_string()).or_default() += 1;
+        inner
+            .process_seconds
+            .entry(kind.to_string())
+            .or_default()
+            .observe(&PROCESS_BUCKETS, duration.as_secs_f64());
+    }
+
+    pub fn record_error(&self, error: &Error) {
+        *self
+            .inner
+            .lock()
+            .unwrap()
+            .errors
+            .entry(error.code().to_string())
+            .or_default() += 1;
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let inner = self.inner.lock().unwrap();
+        MetricsSnapshot {
+            hosts: inner.hosts.clone(),
+            items: inner.items.clone(),
+            errors: inner.errors.clone(),
+            fetches_in_flight: inner.fetches_in_flight,
+            items_in_flight: inner.items_in_flight,
+        }
+    }
+
+    /// The metrics in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let inner = self.inner.lock().unwrap();
+        let mut out = String::new();
+
+        header(
+            &mut out,
+            "omvarlden_fetches_total",
+            "counter",
+            "Responses received, by host and HTTP status.",
+        );
+        for ((host, status), count) in &inner.fetches {
+            let _ = writeln!(
+                out,
+                "omvarlden_fetches_total{{host=\"{}\",status=\"{}\"}} {}",
+                escape(host),
+                status,
+                count
+            );
+        }
+        header(
+            &mut out,
+            "omvarlden_downloaded_bytes_total",
+            "counter",
+            "Bytes of content downloaded, by host.",
+        );
+        for (host, metrics) in &inner.hosts {
+            let _ = writeln!(
+                out,
+                "omvarlden_downloaded_bytes_total{{host=\"{}\"}} {}",
+                escape(host),
+                metrics.bytes
+            );
+        }
+        header(
+            &mut out,
+            "omvarlden_discovered_urls_total",
+            "counter",
+            "Links found on fetched pages, before deduplication.",
+        );
+        let _ = writeln!(
+            out,
+            "omvarlden_discovered_urls_total {}",
+            inner.discovered_urls
+        );
+        header(
+            &mut out,
+            "omvarlden_known_urls",
+            "gauge",
+            "Distinct URLs seen, by host.",
+        );
+        for (host, metrics) in &inner.hosts {
+            let _ = writeln!(
+                out,
+                "omvarlden_known_urls{{host=\"{}\"}} {}",
+                escape(host),
+                metrics.known_urls
+            );
+        }
+        header(
+            &mut out,
+            "omvarlden_visited_urls_total",
+            "counter",
+            "URLs fetched, by host.",
+        );
+        for (host, metrics) in &inner.hosts {
+            let _ = writeln!(
+                out,
+                "omvarlden_visited_urls_total{{host=\"{}\"}} {}",
+                escape(host),
+                metrics.visited_urls
+            );
+        }
+        header(
+            &mut out,
+            "omvarlden_items_total",
+            "counter",
+            "Items processed, by kind.",
+        );
+        for (kind, count) in &inner.items {
+            let _ = writeln!(out, "omvarlden_items_total{{kind=\"{}\"}} {}", kind, count);
+        }
+        header(
+            &mut out,
+            "omvarlden_errors_total",
+            "counter",
+            "Errors, by error code.",
+        );
+        for (code, count) in &inner.errors {
+            let _ = writeln!(out, "omvarlden_errors_total{{code=\"{}\"}} {}", code, count);
+        }
+        header(
+            &mut out,
+            "omvarlden_fetch_seconds",
+            "histogram",
+            "Time until the response headers are received.",
+        );
+        inner
+            .fetch_seconds
+            .render(&mut out, "omvarlden_fetch_seconds", "", &FETCH_BUCKETS);
+        header(
+            &mut out,
+            "omvarlden_process_seconds",
+            "histogram",
+            "Time to process an item, by kind.",
+        );
+        for (kind, histogram) in &inner.process_seconds {
+            histogram.render(
+                &mut out,
+                "omvarlden_process_seconds",
+                &format!("kind=\"{}\",", kind),
+                &PROCESS_BUCKETS,
+            );
+        }
+        header(
+            &mut out,
+            "omvarlden_fetches_in_flight",
+            "gauge",
+            "Fetches in progress.",
+        );
+        let _ = writeln!(
+            out,
+            "omvarlden_fetches_in_flight {}",
+            inner.fetches_in_flight
+        );
+        header(
+            &mut out,
+            "omvarlden_items_in_flight",
+            "gauge",
+            "Items being processed.",
+        );
+        let _ = writeln!(out, "omvarlden_items_in_flight {}", inner.items_in_flight);
+        out
+    }
+
+    /// Write the metrics to `path`, replacing it atomically as node_exporter expects.
+    pub fn write_textfile(&self, path: &Path) -> Result<(), Error> {
+        write_atomic(path, self.render().as_bytes())
+    }
+
+    /// Write the metrics to `path` every `interval` from a background thread.
+    pub fn write_textfile_every(self: Arc<Self>, path: PathBuf, interval: Duration) {
+        thread::spawn(move || loop {
+            if let Err(error) = self.write_textfile(&path) {
+                tracing::warn!("failed writing metrics: {:#}", error);
+            }
+            thread::sleep(interval);
+        });
+    }
+
+    /// Serve the metrics at `http://addr/metrics` from background threads, one
+    /// per connection so a slow scraper doesn't hold up the others.
+    pub fn serve(self: Arc<Self>, addr: SocketAddr) -> io::Result<()> {
+        let listener = TcpListener::bind(addr)?;
+        tracing::info!("serving metrics on http://{}/metrics", addr);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(error) => {
+                        tracing::debug!("failed accepting metrics request: {}", error);
+                        continue;
+                    }
+                };
+                let metrics = self.clone();
+                thread::spawn(move || {
+                    if let Err(error) = metrics.respond(stream) {
+                        tracing::debug!("metrics request failed: {}", error);
+                    }
+                });
+            }
+        });
+        Ok(())
+    }
+
+    fn respond(&self, mut stream: TcpStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+        stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+        // a scraper trickling headers can't keep the connection open for long
+        let mut reader = io::BufReader::new(stream.try_clone()?.take(MAX_REQUEST_BYTES));
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+        // skip the headers
+        let mut line = String::new();
+        while reader.read_line(&mut line)? > 2 {
+            line.clear();
+        }
+        let path = request_line.split_whitespace().nth(1).unwrap_or_default();
+        let (status, body) = if path == "/metrics" {
+            ("200 OK", self.render())
+        } else {
+            ("404 Not Found", "not found\n".to_string())
+        };
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        )
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn host(url: &str) -> String {
+    Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_string))
+        .unwrap_or_default()
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -1,16 +1,59 @@
-use std::{fmt, fs, io::Write, path::PathBuf};
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Instant,
+};
 
 use async_trait::async_trait;
 use flate2::Compression;
 use reqwest::{Client, Url};
 use scraper::{Html, Selector};
+use tokio::runtime::{Handle, RuntimeFlavor};
 
-use crate::item::Item;
+use crate::archive::{self, Archive, ArchivePage};
+use crate::article::Article;
+use crate::audio::AudioMetadata;
+use crate::budget::{BudgetTracker, CrawlBudget};
+use crate::date;
+use crate::failures::{FailureLog, Stage};
+use crate::files::read_lines;
+use crate::frontier::{self, Frontier, PriorityFn};
+use crate::item::{FetchInfo, Item};
+use crate::manifest::{ContentDigest, CrawlManifest, ManifestEntry, ManifestOutput};
+use crate::metrics::Metrics;
+use crate::paths;
+use crate::shutdown::{Shutdown, ShutdownReason};
+use crate::stats::{CrawlStats, CrawlSummary};
+use crate::storage::{DryRunReport, DryRunReporter, DryRunStorage, Storage, StorageKind};
 use crate::Error;
 
 pub struct OmvarldenSpider {
     http_client: Client,
-    output_path: PathBuf,
+    storage: Box<dyn Storage>,
+    manifest: CrawlManifest,
+    failures: FailureLog,
+    stats: CrawlStats,
+    metrics: Arc<Metrics>,
+    shutdown: Shutdown,
+    budget: BudgetTracker,
+    frontier: Frontier,
+    archive: Archive,
+    dry_run: Option<DryRunReporter>,
+    episodes: Mutex<Option<Episodes>>,
+    start_urls: Vec<String>,
+    follow_links: bool,
+}
+
+/// Bookkeeping for merging audio metadata into the episodes' metadata documents.
+#[derive(Debug, Default)]
+struct Episodes {
+    /// mp3 url -> key of the episode's metadata document.
+    meta_keys: HashMap<String, String>,
+    /// Audio metadata of episodes downloaded before their metadata was seen.
+    pending_audio: HashMap<String, serde_json::Value>,
 }
 
 impl fmt::Debug for OmvarldenSpider {
@@ -23,6 +66,25 @@ impl fmt::Debug for OmvarldenSpider {
 pub struct OmvarldenSpiderOptions {
     pub user_agent: Option<String>,
     pub output_path: PathBuf,
+    pub storage: StorageKind,
+    /// URLs to start from instead of the front pages of the site and podcast.
+    pub start_urls: Option<Vec<String>>,
+    /// More URLs to start from, besides `start_urls` or the front pages.
+    pub seeds: Vec<String>,
+    /// Whether to follow links. If not, only links to podcast episodes that
+    /// are not stored yet are followed, which is used for retrying failed URLs
+    /// without crawling the site again.
+    pub follow_links: bool,
+    /// Limits after which the crawl is shut down.
+    pub budget: CrawlBudget,
+    /// Scores URLs for the [`Frontier`], `None` returns URLs as they are found.
+    pub priority: Option<PriorityFn>,
+    /// How many held back URLs the frontier releases at a time, should be the
+    /// crawling concurrency.
+    pub frontier_batch: usize,
+    /// Plan what would be stored instead of storing it, see
+    /// [`DryRunStorage`]. Nothing is written to the output directory.
+    pub dry_run: bool,
 }
 
 impl Default for OmvarldenSpiderOptions {
@@ -30,46 +92,442 @@ impl Default for OmvarldenSpiderOptions {
         Self {
             user_agent: None,
             output_path: "./output".into(),
+            storage: StorageKind::default(),
+            start_urls: None,
+            seeds: Vec::new(),
+            follow_links: true,
+            budget: CrawlBudget::default(),
+            priority: Some(default_priority),
+            frontier_batch: 2,
+            dry_run: false,
         }
     }
 }
 
 impl OmvarldenSpider {
-    const BASE_URL: &'static str = "https://www.omvarlden.se";
+    pub(crate) const BASE_URL: &'static str = "https://www.omvarlden.se";
     const PODD_META_URL: &'static str = "https://utvecklingssamtalet.libsyn.com";
     const PODD_URL: &'static str = "https://traffic.libsyn.com";
 
-    pub fn new(
+    pub fn new(options: OmvarldenSpiderOptions) -> Result<Self, Error> {
+        if options.dry_run {
+            let output_path = Self::existing_output_path(&options.output_path);
+            let storage = DryRunStorage::new(options.storage, &output_path);
+            let reporter = storage.reporter();
+            let mut spider = Self::with_storage(options, Box::new(storage))?;
+            spider.dry_run = Some(reporter);
+            return Ok(spider);
+        }
+        let output_path = Self::create_output_path(&options.output_path)?;
+        let storage = options.storage.open(&output_path)?;
+        Self::with_storage(options, storage)
+    }
+
+    /// Create a spider writing to `storage`, ignoring `options.storage`.
+    ///
+    /// The crawl manifest and failures are still written to `options.output_path`.
+    pub fn with_storage(
         OmvarldenSpiderOptions {
             user_agent,
             output_path,
+            storage: _,
+            start_urls,
+            seeds,
+            follow_links,
+            budget,
+            priority,
+            frontier_batch,
+            dry_run,
         }: OmvarldenSpiderOptions,
+        storage: Box<dyn Storage>,
     ) -> Result<Self, Error> {
-        tracing::info!("creating {}, if not exists", output_path.display());
-        fs::create_dir_all(&output_path).map_err(|error| Error::CantCreateDir {
-            path: output_path.clone(),
-            error,
-        })?;
-        let output_path =
-            output_path
-                .canonicalize()
-                .map_err(|error| Error::CantCanonicalizePath {
-                    path: output_path,
-                    error,
-                })?;
-        let user_agent = user_agent.as_deref().unwrap_or(crate::APP_USER_AGENT);
-        tracing::warn!(user_agent, "configuring SfsSpider {:?}", output_path);
-        let http_client = reqwest::Client::builder()
-            .user_agent(user_agent)
-            .gzip(true)
-            .build()
-            .map_err(|err| Error::CantCreateHttpClient(err))?;
+        tracing::warn!(user_agent, "configuring OmvarldenSpider");
+        let http_client = http_client(user_agent.as_deref())?;
+        let (output_path, manifest, failures) = if dry_run {
+            let output_path = Self::existing_output_path(&output_path);
+            let manifest = CrawlManifest::dry_run(&output_path);
+            let failures = FailureLog::dry_run(&output_path)?;
+            (output_path, manifest, failures)
+        } else {
+            let output_path = Self::create_output_path(&output_path)?;
+            let manifest = CrawlManifest::open(&output_path)?;
+            let failures = FailureLog::open(&output_path)?;
+            (output_path, manifest, failures)
+        };
+        let (frontier, archive) = if dry_run {
+            (
+                Frontier::dry_run(&output_path, priority, frontier_batch)?,
+                Archive::dry_run(&output_path)?,
+            )
+        } else {
+            (
+                Frontier::open(&output_path, priority, frontier_batch)?,
+                Archive::open(&output_path)?,
+            )
+        };
+        let mut start_urls = match start_urls {
+            Some(start_urls) => start_urls,
+            None => {
+                let mut start_urls = vec![
+                    Self::BASE_URL.to_string(),
+                    "https://utvecklingssamtalet.libsyn.com/page/1/size/200".to_string(),
+                ];
+                start_urls.extend(Archive::start_urls());
+                // held back by the frontier when an earlier crawl stopped
+                start_urls.extend(frontier.saved_urls());
+                start_urls
+            }
+        };
+        start_urls.extend(seeds);
         Ok(Self {
             http_client,
-            output_path,
+            storage,
+            manifest,
+            failures,
+            stats: CrawlStats::new(),
+            metrics: Arc::new(Metrics::new()),
+            shutdown: Shutdown::new(),
+            budget: BudgetTracker::new(budget),
+            frontier,
+            archive,
+            dry_run: None,
+            episodes: Mutex::new(None),
+            start_urls,
+            follow_links,
+        })
+    }
+
+    /// The output path, made absolute if it exists, without creating it.
+    fn existing_output_path(output_path: &Path) -> PathBuf {
+        output_path
+            .canonicalize()
+            .unwrap_or_else(|_| output_path.to_path_buf())
+    }
+
+    fn create_output_path(output_path: &Path) -> Result<PathBuf, Error> {
+        tracing::info!("creating {}, if not exists", output_path.display());
+        fs::create_dir_all(output_path).map_err(|error| Error::IoCreate {
+            path: output_path.to_path_buf(),
+            source: error,
+        })?;
+        // not retryable, the directory was just created
+        output_path.canonicalize().map_err(|error| Error::Config {
+            what: "output path",
+            source: format!("'{}': {}", output_path.display(), error).into(),
+        })
+    }
+
+    /// Flush the storage and save the URLs held back by the frontier, call
+    /// once when the crawl is done.
+    pub fn finish(&self) -> Result<(), Error> {
+        self.frontier.save()?;
+        self.storage.finish()
+    }
+
+    /// Metrics of the crawl, to be served or written while crawling.
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+
+    /// The handle for stopping the crawl, whose [`Shutdown::wait`] is to be
+    /// passed as the signal to `webcrawler`.
+    pub fn shutdown(&self) -> Shutdown {
+        self.shutdown.clone()
+    }
+
+    /// What a dry run would have written, `None` if this isn't a dry run.
+    pub fn dry_run_report(&self) -> Option<DryRunReport> {
+        self.dry_run.as_ref().map(DryRunReporter::report)
+    }
+
+    /// Statistics of the crawl so far.
+    pub fn summary(&self) -> CrawlSummary {
+        self.stats.summary()
+    }
+
+    /// Record a failure of `url` in the statistics, metrics and failures file.
+    fn record_failure(&self, url: &str, stage: Stage, error: &Error) {
+        self.stats.record_error(url, error);
+        self.metrics.record_error(error);
+        if let Err(log_error) = self.failures.record(url, stage, error) {
+            tracing::error!("failed recording failure of '{}': {:#}", url, log_error);
+        }
+    }
+
+    /// Append `entry` to the crawl manifest and count the item, unless it was
+    /// skipped, the same way the summary of an output directory counts it.
+    fn record_item(&self, entry: &ManifestEntry) -> Result<(), Error> {
+        self.manifest.record(entry)?;
+        if entry.skipped.is_none() {
+            self.stats.record_item(entry.kind);
+        }
+        Ok(())
+    }
+
+    /// Whether to follow a link to `url` when not following links in general.
+    fn is_missing_episode(&self, url: &str) -> bool {
+        url.starts_with(Self::PODD_URL)
+            && Url::parse(url)
+                .is_ok_and(|url| !self.storage.exists(&podd_key(&url)).unwrap_or(false))
+    }
+
+    /// Read audio metadata from a downloaded episode, recording failures in the JSON
+    /// instead of failing so that broken downloads are visible in the metadata.
+    fn read_audio_metadata(path: &Path) -> serde_json::Value {
+        let result = fs::File::open(path)
+            .map_err(|error| Error::IoRead {
+                path: path.to_path_buf(),
+                source: error,
+            })
+            .and_then(|file| AudioMetadata::from_reader(std::io::BufReader::new(file)));
+        audio_json(result, &path.display().to_string())
+    }
+
+    /// Scan `podd_meta` for episode metadata written by earlier runs.
+    fn load_episodes(&self) -> Result<Episodes, Error> {
+        let mut episodes = Episodes::default();
+        for key in self.storage.list(PODD_META_PREFIX)? {
+            if !key.ends_with(".json") {
+                continue;
+            }
+            match self.storage.get_document(&key) {
+                Ok(Some(json)) => {
+                    if let Some(mp3_url) = json["mp3_url"].as_str() {
+                        episodes.meta_keys.insert(mp3_url.to_string(), key);
+                    }
+                }
+                Ok(None) => {}
+                Err(error) => tracing::warn!("{:#}", error),
+            }
+        }
+        tracing::debug!("loaded {} episodes", episodes.meta_keys.len());
+        Ok(episodes)
+    }
+}
+
+fn audio_json(result: Result<AudioMetadata, Error>, source: &str) -> serde_json::Value {
+    match result {
+        Ok(audio) => audio.to_json(),
+        Err(error) => {
+            tracing::warn!("failed reading audio metadata from '{}': {}", source, error);
+            serde_json::json!({ "error": error.to_string() })
+        }
+    }
+}
+
+const PODD_META_PREFIX: &str = "podd_meta/";
+
+/// The key a page is stored under, `None` for section index pages, which only
+/// list articles.
+pub(crate) fn html_key(url: &Url) -> Option<String> {
+    if archive::index_section(url).is_some() {
+        return None;
+    }
+    Some(paths::page_key(url))
+}
+
+pub(crate) fn http_client(user_agent: Option<&str>) -> Result<Client, Error> {
+    reqwest::Client::builder()
+        .user_agent(user_agent.unwrap_or(crate::APP_USER_AGENT))
+        .gzip(true)
+        .build()
+        .map_err(|error| Error::Config {
+            what: "http client",
+            source: Box::new(error),
         })
+}
+
+/// The items in `body`, fetched from `url`, and the URLs it links to.
+pub(crate) fn extract(
+    archive: &Archive,
+    url: &str,
+    fetch: FetchInfo,
+    body: Vec<u8>,
+) -> (Vec<Item>, Vec<String>) {
+    let mut new_urls = Vec::new();
+    let mut items = Vec::new();
+    let archive_section = Url::parse(url)
+        .ok()
+        .and_then(|parsed| Some((archive.section(&parsed)?, parsed)));
+    if let Some((section, page_url)) = archive_section {
+        let text = String::from_utf8_lossy(&body).into_owned();
+        let is_json = fetch
+            .content_type
+            .as_deref()
+            .is_some_and(|content_type| content_type.contains("json"));
+        let page = if is_json {
+            // "load more" responses aren't stored, only their links are followed
+            let json = serde_json::from_str(&text).unwrap_or_else(|error| {
+                tracing::warn!("failed parsing archive page '{}': {}", url, error);
+                serde_json::Value::Null
+            });
+            ArchivePage::from_json(&page_url, &json)
+        } else {
+            let page = ArchivePage::from_html(&page_url, &text);
+            items.push(Item::Html { fetch, html: text });
+            page
+        };
+        new_urls.extend(archive.follow(section, &page_url, page));
+    } else if url.starts_with(OmvarldenSpider::BASE_URL) {
+        let text = String::from_utf8_lossy(&body).into_owned();
+        let document = Html::parse_document(&text);
+        let a_selector = Selector::parse("a").unwrap();
+        for link in document.select(&a_selector) {
+            let href = link.attr("href");
+            if let Some(href) = href {
+                if href.starts_with("/") {
+                    new_urls.push(format!("{}{}", OmvarldenSpider::BASE_URL, href));
+                }
+            }
+        }
+        tracing::debug!(new_urls = ?new_urls);
+        items.push(Item::Html { fetch, html: text });
+    } else if url.starts_with(OmvarldenSpider::PODD_META_URL) {
+        let text = String::from_utf8_lossy(&body).into_owned();
+        let document = Html::parse_document(&text);
+        let a_selector = Selector::parse("a").unwrap();
+        let item_selector = Selector::parse(r#"div[class="libsyn-item"]"#).unwrap();
+        let title_selector = Selector::parse(r#"div[class="libsyn-item-title"]"#).unwrap();
+        let release_date_selector =
+            Selector::parse(r#"div[class="libsyn-item-release-date"]"#).unwrap();
+        let body_selector = Selector::parse(r#"div[class="libsyn-item-body"]"#).unwrap();
+        let content_selector = Selector::parse(r#"div[class="libsyn-item-content"]"#).unwrap();
+        for item in document.select(&item_selector) {
+            let title = item.select(&title_selector).next().unwrap();
+            let a = title.select(&a_selector).next().unwrap();
+            let title: String = a.text().collect();
+            let meta_url = if let Some(href) = a.attr("href") {
+                href.to_string()
+            } else {
+                String::new()
+            };
+            let release_date_div = item.select(&release_date_selector).next().unwrap();
+            let release_date: String = release_date_div.text().collect();
+            let description = item
+                .select(&body_selector)
+                .next()
+                .map(|body_div| body_div.text().collect::<String>());
+            let content_div = item.select(&content_selector).next().unwrap();
+            let a = content_div.select(&a_selector).next().unwrap();
+            let mp3_url = if let Some(href) = a.attr("href") {
+                href.to_string()
+            } else {
+                String::new()
+            };
+            tracing::debug!(mp3_url, "found episode");
+            new_urls.push(mp3_url.clone());
+            items.push(Item::PoddMeta {
+                fetch: fetch.clone(),
+                meta_url,
+                title,
+                release_date,
+                description,
+                mp3_url,
+            });
+        }
+    } else if url.starts_with(OmvarldenSpider::PODD_URL) {
+        tracing::debug!("downloaded mp3 from '{}'", url);
+        items.push(Item::Podd { fetch, data: body });
+    } else {
+        tracing::error!("unknown url '{}'", url);
+    }
+    (items, new_urls)
+}
+
+/// Read seed URLs from `path`, one per line like the requeue file written by
+/// `verify`. Blank lines and lines starting with `#` are skipped.
+pub fn read_seeds(path: &Path) -> Result<Vec<String>, Error> {
+    let mut seeds = Vec::new();
+    for line in read_lines(path)? {
+        let line = line.trim();
+        if line.starts_with('#') {
+            continue;
+        }
+        let seed = Url::parse(line).map_err(|error| Error::Config {
+            what: "seeds",
+            source: format!("'{}' in '{}': {}", line, path.display(), error).into(),
+        })?;
+        seeds.push(seed.to_string());
+    }
+    Ok(seeds)
+}
+
+/// Check that `seed` is an absolute URL, and normalize it.
+pub fn parse_seed(seed: &str) -> Result<String, Error> {
+    let url = Url::parse(seed.trim()).map_err(|error| Error::Config {
+        what: "seed",
+        source: Box::new(error),
+    })?;
+    Ok(url.to_string())
+}
+
+/// The key a page is stored under, `None` for the pages of section archives.
+pub(crate) fn page_key(archive: &Archive, url: &Url) -> Option<String> {
+    html_key(url).filter(|_| archive.section(url).is_none())
+}
+
+/// First path segments of the pages listing articles by tag, topic or author.
+const LISTING_PREFIXES: [&str; 9] = [
+    "tagg",
+    "tag",
+    "amne",
+    // ämne
+    "%C3%A4mne",
+    "tema",
+    "kategori",
+    "category",
+    "skribent",
+    "author",
+];
+
+/// The default [`PriorityFn`]: articles first, then pages listing articles or
+/// episodes, then podcast audio.
+pub fn default_priority(url: &Url) -> u32 {
+    if url.as_str().starts_with(OmvarldenSpider::PODD_URL) {
+        frontier::AUDIO
+    } else if url.as_str().starts_with(OmvarldenSpider::BASE_URL) && !is_listing(url) {
+        frontier::ARTICLE
+    } else {
+        frontier::LISTING
     }
 }
+
+/// Whether `url` lists articles: the front page, a section index, a tag,
+/// topic or author page, or a page of an archive.
+fn is_listing(url: &Url) -> bool {
+    let segments: Vec<&str> = url
+        .path_segments()
+        .into_iter()
+        .flatten()
+        .filter(|segment| !segment.is_empty())
+        .collect();
+    let Some(first) = segments.first() else {
+        return true;
+    };
+    url.query().is_some()
+        || html_key(url).is_none()
+        || LISTING_PREFIXES
+            .iter()
+            .any(|prefix| first.eq_ignore_ascii_case(prefix))
+        || segments.contains(&"page")
+}
+
+/// The key the episode downloaded from `mp3_url` is stored under.
+pub(crate) fn podd_key(mp3_url: &Url) -> String {
+    paths::sanitize_key(&mp3_url.path()[1..])
+}
+
+/// The key of the metadata document of the episode described at `meta_url`.
+pub(crate) fn podd_meta_key(meta_url: &Url) -> String {
+    let key = Path::new(&meta_url.path()[1..]).with_extension("json");
+    paths::sanitize_key(&format!("{}{}", PODD_META_PREFIX, key.to_string_lossy()))
+}
+
+/// Where the episode downloaded from `mp3_url` is stored in the tree layout.
+pub(crate) fn podd_path(output_path: &Path, mp3_url: &Url) -> PathBuf {
+    paths::key_path(output_path, &podd_key(mp3_url))
+}
+
 #[async_trait]
 impl webcrawler::Spider for OmvarldenSpider {
     type Item = Item;
@@ -80,244 +538,201 @@ impl webcrawler::Spider for OmvarldenSpider {
     }
 
     fn start_urls(&self) -> Vec<String> {
-        vec![
-            Self::BASE_URL.to_string(),
-            "https://utvecklingssamtalet.libsyn.com/page/1/size/200".to_string(),
-        ]
+        self.start_urls.clone()
     }
 
     #[tracing::instrument]
     async fn scrape(&self, url: String) -> Result<(Vec<Self::Item>, Vec<String>), Self::Error> {
-        let mut new_urls = Vec::new();
-        let mut items = Vec::new();
+        // start urls aren't found as links
+        let new = self.frontier.see(&url);
+        let _in_flight = self.metrics.start_fetch(&url, new);
+        self.budget.record_fetch();
+        self.frontier.begin();
+        let mut result = self.scrape_url(&url).await;
+        match &mut result {
+            Ok((_, new_urls)) => {
+                if !self.follow_links {
+                    new_urls.retain(|new_url| self.is_missing_episode(new_url));
+                }
+                self.budget.filter_links(&url, new_urls);
+                let found = new_urls.len();
+                new_urls.retain(|new_url| self.frontier.see(new_url));
+                self.metrics.record_discovered(found, new_urls);
+                *new_urls = self.frontier.end(&url, std::mem::take(new_urls));
+                if let Err(error) = self.failures.resolve(&url) {
+                    tracing::error!("failed recording retry of '{}': {:#}", url, error);
+                }
+            }
+            Err(error) => {
+                self.record_failure(&url, Stage::Scrape, error);
+                // urls can't be returned with an error, the failure is recorded
+                // already, so return the released urls instead
+                let released = self.frontier.end_failed(&url);
+                if !released.is_empty() {
+                    result = Ok((Vec::new(), released));
+                }
+            }
+        }
+        if let Some(budget) = self.budget.exceeded() {
+            self.shutdown.trigger(ShutdownReason::Budget(budget));
+        }
+        result
+    }
+
+    #[tracing::instrument(skip(item))]
+    async fn process(&self, url: String, item: Self::Item) -> Result<String, Error> {
+        let _in_flight = self.metrics.start_item();
+        let started = Instant::now();
+        let kind = item.kind();
+        // storing writes and fsyncs whole episodes, so on the multi-threaded
+        // runtime hand the other tasks of this worker to another one, a
+        // current-thread runtime has nowhere to move them
+        let result = match Handle::try_current().map(|handle| handle.runtime_flavor()) {
+            Ok(RuntimeFlavor::MultiThread) => {
+                tokio::task::block_in_place(|| self.process_item(&url, item))
+            }
+            _ => self.process_item(&url, item),
+        };
+        match &result {
+            Ok(_) => self.metrics.record_item(kind, started.elapsed()),
+            Err(error) => self.record_failure(&url, Stage::Process, error),
+        }
+        result
+    }
+}
+
+impl OmvarldenSpider {
+    async fn scrape_url(&self, url: &str) -> Result<(Vec<Item>, Vec<String>), Error> {
+        let url = url.to_string();
 
         tracing::info!("calling {}", url);
+        let started = Instant::now();
         let response = self.http_client.get(&url).send().await.map_err(|error| {
             tracing::error!("Failed fetching: {:?}", error);
-            Error::ScrapeError {
+            Error::Fetch {
                 url: url.clone(),
-                error,
+                source: error,
             }
         })?;
 
         tracing::trace!("response status: {}", response.status());
+        let host = response.url().host_str().unwrap_or_default().to_string();
+        self.stats.record_fetch(response.status().as_u16());
+        self.metrics
+            .record_fetch(&host, response.status().as_u16(), started.elapsed());
+        let fetch = FetchInfo::from_response(&url, &response);
+        if let Err(error) = self.storage.record_fetch(&fetch) {
+            tracing::warn!("failed recording fetch of '{}': {:#}", url, error);
+        }
         if !response.status().is_success() {
-            let status_code = response.status();
+            let status = response.status();
             tracing::error!(
                 "The request returned '{}': '{}",
-                response.status(),
-                response.text().await.map_err(|error| Error::ScrapeError {
+                status,
+                response.text().await.map_err(|error| Error::Fetch {
                     url: url.clone(),
-                    error
+                    source: error
                 })?
             );
-            return Err(Error::RequestReturnedError { url, status_code });
+            return Err(Error::HttpStatus { url, status });
         }
 
-        if url.starts_with(Self::BASE_URL) {
-            let text = response.text().await.map_err(|error| {
-                tracing::error!("Failed getting text: {}", error);
-                Error::FailedToGetData {
-                    url: url.clone(),
-                    error,
-                }
-            })?;
-            let document = Html::parse_document(&text);
-            let a_selector = Selector::parse("a").unwrap();
-            for link in document.select(&a_selector) {
-                let href = link.attr("href");
-                if let Some(href) = href {
-                    if href.starts_with("/") {
-                        new_urls.push(format!("{}{}", Self::BASE_URL, href));
-                    }
-                }
-            }
-            tracing::debug!(new_urls = ?new_urls);
-            items.push(Item::Html(text));
-        } else if url.starts_with(Self::PODD_META_URL) {
-            let text = response.text().await.map_err(|error| {
-                tracing::error!("Failed getting text: {}", error);
-                Error::FailedToGetData {
-                    url: url.clone(),
-                    error,
-                }
-            })?;
-            let document = Html::parse_document(&text);
-            let a_selector = Selector::parse("a").unwrap();
-            let item_selector = Selector::parse(r#"div[class="libsyn-item"]"#).unwrap();
-            let title_selector = Selector::parse(r#"div[class="libsyn-item-title"]"#).unwrap();
-            let release_date_selector =
-                Selector::parse(r#"div[class="libsyn-item-release-date"]"#).unwrap();
-            let body_selector = Selector::parse(r#"div[class="libsyn-item-body"]"#).unwrap();
-            let content_selector = Selector::parse(r#"div[class="libsyn-item-content"]"#).unwrap();
-            for item in document.select(&item_selector) {
-                let title = item.select(&title_selector).next().unwrap();
-                let a = title.select(&a_selector).next().unwrap();
-                let title: String = a.text().collect();
-                let meta_url = if let Some(href) = a.attr("href") {
-                    href.to_string()
-                } else {
-                    String::new()
-                };
-                let release_date_div = item.select(&release_date_selector).next().unwrap();
-                let release_date: String = release_date_div.text().collect();
-                let description = if let Some(body_div) = item.select(&body_selector).next() {
-                    Some(body_div.text().collect::<String>())
-                } else {
-                    None
-                };
-                let content_div = item.select(&content_selector).next().unwrap();
-                let a = content_div.select(&a_selector).next().unwrap();
-                let mp3_url = if let Some(href) = a.attr("href") {
-                    href.to_string()
-                } else {
-                    String::new()
-                };
-                dbg!(&mp3_url);
-                new_urls.push(mp3_url.clone());
-                items.push(Item::PoddMeta {
-                    meta_url,
-                    title,
-                    release_date,
-                    description,
-                    mp3_url,
-                });
+        let body = response.bytes().await.map_err(|error| {
+            tracing::error!("Failed getting body: {}", error);
+            Error::Fetch {
+                url: url.clone(),
+                source: error,
             }
-        } else if url.starts_with(Self::PODD_URL) {
-            tracing::debug!("downloading mp3 from '{}'", url);
-            let mp3_data = response.bytes().await.map_err(|error| {
-                tracing::error!("Failed getting bytes: {}", error);
-                Error::FailedToGetData {
-                    url: url.clone(),
-                    error,
-                }
-            })?;
-            items.push(Item::Podd(mp3_data.to_vec()));
-        } else {
-            tracing::error!("unknown url '{}'", url);
-        }
-        Ok((items, new_urls))
+        })?;
+        self.stats.record_bytes(body.len());
+        self.metrics.record_bytes(&host, body.len());
+        self.budget
+            .record_bytes(body.len(), url.starts_with(Self::PODD_URL));
+        Ok(extract(&self.archive, &url, fetch, body.to_vec()))
     }
 
-    #[tracing::instrument(skip(item))]
-    async fn process(&self, url: String, item: Self::Item) -> Result<String, Error> {
-        let mut path = self.output_path.clone();
-        tracing::debug!(path = ?path);
+    fn process_item(&self, url: &str, item: Item) -> Result<String, Error> {
         tracing::info!(url, "analyzing url");
-        let url = Url::parse(&url).unwrap();
+        let url = Url::parse(url).unwrap();
+        let mut entry = ManifestEntry::new(item.kind(), item.fetch().clone());
         match item {
-            Item::Html(item) => {
-                let file_name = match url.path() {
-                    "/" => "index.html",
-                    x => {
-                        let parts: Vec<&str> = x.split('/').collect();
-                        let mut result = parts[0];
-                        let parts_len = parts.len();
-                        for (i, part) in parts.into_iter().enumerate() {
-                            if i == parts_len - 1 {
-                                result = part;
-                            } else {
-                                if !part.is_empty() {
-                                    path.push(part);
-                                }
-                            }
-                        }
-                        result
+            Item::Html { fetch, html } => {
+                entry = entry.with_content(&ContentDigest::of(html.as_bytes()));
+                let Some(key) = page_key(&self.archive, &url) else {
+                    entry.skipped = Some(if archive::index_section(&url).is_some() {
+                        "section index"
+                    } else {
+                        "archive page"
+                    });
+                    self.record_item(&entry)?;
+                    return Ok(String::new());
+                };
+                tracing::debug!(key, "final key");
+                let compress_error = |error| {
+                    tracing::error!("failed compressing html, url={}", url);
+                    Error::IoWrite {
+                        path: key.clone().into(),
+                        source: error,
                     }
                 };
-                tracing::debug!(file_name = ?file_name);
-                tokio::fs::create_dir_all(&path).await.map_err(|error| {
-                    tracing::error!(
-                        "failed creating path='{}', url={}, error={}",
-                        path.display(),
-                        url,
-                        error
-                    );
-                    Error::CantCreateDir {
-                        path: path.clone(),
-                        error,
+                let mut encoder = flate2::write::GzEncoder::new(Vec::new(), Compression::best());
+                tracing::info!("writing html");
+                encoder.write_all(html.as_bytes()).map_err(compress_error)?;
+                let compressed = encoder.finish().map_err(compress_error)?;
+                let article = Article::from_html(&html, fetch.fetched_at);
+                self.storage.put_page(&key, &fetch, &compressed, &article)?;
+                let local_path = self.storage.local_path(&key);
+                entry.outputs.push(ManifestOutput::new(
+                    &key,
+                    local_path.clone(),
+                    &ContentDigest::of(&compressed),
+                ));
+                self.record_item(&entry)?;
+                Ok(local_path
+                    .map(|path| path.display().to_string())
+                    .unwrap_or(key))
+            }
+            Item::Podd {
+                fetch: _,
+                data: podd_mp3,
+            } => {
+                let key = podd_key(&url);
+                tracing::debug!(key, "final key");
+                let digest = ContentDigest::of(&podd_mp3);
+                self.storage
+                    .put_hashed_blob(&key, url.as_str(), &podd_mp3, &digest.sha256)?;
+                entry.outputs.push(ManifestOutput::new(
+                    &key,
+                    self.storage.local_path(&key),
+                    &digest,
+                ));
+                entry = entry.with_content(&digest);
+
+                let audio = audio_json(AudioMetadata::from_bytes(&podd_mp3), url.as_str());
+                let mut episodes = self.episodes.lock().unwrap();
+                let episodes = match episodes.as_mut() {
+                    Some(episodes) => episodes,
+                    None => episodes.insert(self.load_episodes()?),
+                };
+                if let Some(meta_key) = episodes.meta_keys.get(url.as_str()) {
+                    if let Some(mut json_data) = self.storage.get_document(meta_key)? {
+                        json_data["audio"] = audio;
+                        self.storage.put_document(meta_key, &json_data)?;
+                        entry.outputs.push(ManifestOutput::new(
+                            meta_key,
+                            self.storage.local_path(meta_key),
+                            &ContentDigest::of_document(&json_data),
+                        ));
                     }
-                })?;
-                if [
-                    "nyheter",
-                    "globala-malen",
-                    "opinion",
-                    "reportage",
-                    "intervjuer",
-                    "poddar",
-                    "teman",
-                ]
-                .contains(&file_name)
-                {
-                    Ok(String::new())
                 } else {
-                    let mut path = path.join(file_name);
-                    path.set_extension("html.gz");
-                    tracing::debug!( path = ?path, "final path");
-                    let file = std::fs::File::create(&path).map_err(|error| {
-                        tracing::error!(
-                            "failed creating '{}', url={}, error={}",
-                            path.display(),
-                            url,
-                            error
-                        );
-                        Error::CantCreateDir {
-                            path: path.clone(),
-                            error,
-                        }
-                    })?;
-                    let compress_writer = flate2::write::GzEncoder::new(file, Compression::best());
-                    let mut writer = std::io::BufWriter::new(compress_writer);
-                    tracing::info!("writing html");
-                    writer.write_all(item.as_bytes()).map_err(|error| {
-                        tracing::error!("failed writing '{}', url={}", path.display(), url);
-                        Error::FailedWritingFile {
-                            path: path.clone(),
-                            error,
-                        }
-                    })?;
-                    Ok(path.display().to_string())
+                    tracing::debug!("no episode metadata yet for '{}'", url);
+                    episodes.pending_audio.insert(url.to_string(), audio);
                 }
-            }
-            Item::Podd(podd_mp3) => {
-                let path = path.join(&url.path()[1..]);
-                dbg!(&path);
-                if let Some(parent) = path.parent() {
-                    tokio::fs::create_dir_all(parent).await.map_err(|error| {
-                        tracing::error!(
-                            "failed creating path='{}', url={}, error={}",
-                            parent.display(),
-                            url,
-                            error
-                        );
-                        Error::CantCreateDir {
-                            path: parent.to_path_buf(),
-                            error,
-                        }
-                    })?;
-                }
-                let mut file = std::fs::File::create(&path).map_err(|error| {
-                    tracing::error!(
-                        "failed creating '{}', url={}, error={}",
-                        path.display(),
-                        url,
-                        error
-                    );
-                    Error::CantCreateDir {
-                        path: path.clone(),
-                        error,
-                    }
-                })?;
-                file.write_all(&podd_mp3).map_err(|error| {
-                    tracing::error!("failed writing to '{}': {}", path.display(), error);
-                    Error::FailedWritingFile {
-                        path: path.clone(),
-                        error,
-                    }
-                })?;
+                self.record_item(&entry)?;
                 Ok(String::new())
             }
             Item::PoddMeta {
+                fetch,
                 meta_url,
                 title,
                 release_date,
@@ -325,54 +740,174 @@ impl webcrawler::Spider for OmvarldenSpider {
                 mp3_url,
             } => {
                 let meta_url = Url::parse(&meta_url).unwrap();
-                let path = path.join("podd_meta");
-                let mut path = path.join(&meta_url.path()[1..]);
-                path.set_extension("json");
-                dbg!(&path);
-                if let Some(parent) = path.parent() {
-                    tokio::fs::create_dir_all(parent).await.map_err(|error| {
-                        tracing::error!(
-                            "failed creating path='{}', url={}, error={}",
-                            parent.display(),
-                            url,
-                            error
-                        );
-                        Error::CantCreateDir {
-                            path: parent.to_path_buf(),
-                            error,
-                        }
-                    })?;
-                }
-                let json_data = serde_json::json!(
+                let key = podd_meta_key(&meta_url);
+                tracing::debug!(key, "final key");
+                let release_date_raw = release_date.trim();
+                let release_date = match date::parse_date(release_date_raw, fetch.fetched_at) {
+                    Ok(release_date) => Some(release_date.iso()),
+                    Err(error) => {
+                        tracing::error!("{}, url={}", error, meta_url);
+                        None
+                    }
+                };
+                let fields = serde_json::json!(
                     {
                         "title": title,
+                        "meta_url": meta_url.as_str(),
                         "release_date": release_date,
+                        "release_date_raw": release_date_raw,
                         "description": description,
                         "mp3_url": mp3_url,
                     }
                 );
-                let file = std::fs::File::create(&path).map_err(|error| {
-                    tracing::error!(
-                        "failed creating '{}', url={}, error={}",
-                        path.display(),
-                        url,
-                        error
-                    );
-                    Error::CantCreateDir {
-                        path: path.clone(),
-                        error,
-                    }
-                })?;
-                let mut writer = std::io::BufWriter::new(file);
-                serde_json::to_writer(&mut writer, &json_data).map_err(|error| {
-                    tracing::error!("Failed to write JSON to '{}': {}", path.display(), error);
-                    Error::FailedWritingJson {
-                        path: path.clone(),
-                        error,
+                // keep what was added to the document stored before, like the
+                // articles found by `link`
+                let mut json_data = self
+                    .storage
+                    .get_document(&key)?
+                    .filter(serde_json::Value::is_object)
+                    .unwrap_or_else(|| serde_json::json!({}));
+                for (field, value) in fields.as_object().unwrap() {
+                    json_data[field] = value.clone();
+                }
+                // the episode may already have been downloaded, by this or an earlier run
+                let mut episodes = self.episodes.lock().unwrap();
+                let episodes = match episodes.as_mut() {
+                    Some(episodes) => episodes,
+                    None => episodes.insert(self.load_episodes()?),
+                };
+                if let Some(audio) = episodes.pending_audio.remove(&mp3_url) {
+                    json_data["audio"] = audio;
+                } else if let Ok(mp3_url) = Url::parse(&mp3_url) {
+                    let mp3_key = podd_key(&mp3_url);
+                    if let Some(mp3_path) = self.storage.local_path(&mp3_key) {
+                        if mp3_path.is_file() {
+                            json_data["audio"] = Self::read_audio_metadata(&mp3_path);
+                        }
                     }
-                })?;
+                }
+                self.storage.put_document(&key, &json_data)?;
+                entry.outputs.push(ManifestOutput::new(
+                    &key,
+                    self.storage.local_path(&key),
+                    &ContentDigest::of_document(&json_data),
+                ));
+                self.record_item(&entry)?;
+                episodes.meta_keys.insert(mp3_url, key);
                 Ok(String::new())
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn priority(url: &str) -> u32 {
+        default_priority(&Url::parse(url).unwrap())
+    }
+
+    fn page(path: &str) -> String {
+        format!("{}{}", OmvarldenSpider::BASE_URL, path)
+    }
+
+    #[test]
+    fn articles_first() {
+        assert_eq!(priority(&page("/nyheter/en-artikel/")), frontier::ARTICLE);
+        assert_eq!(priority(&page("/poddar/avsnitt-12/")), frontier::ARTICLE);
+        assert_eq!(
+            priority(&page("/reportage/pagen-i-kenya/")),
+            frontier::ARTICLE
+        );
+    }
+
+    #[test]
+    fn listings_after_articles() {
+        for path in [
+            "/",
+            "/nyheter/",
+            "/poddar",
+            "/tagg/klimat/",
+            "/amne/halsa/",
+            "/%C3%A4mne/h%C3%A4lsa/",
+            "/ämne/hälsa/",
+            "/Tema/vatten/",
+            "/skribent/anna-andersson/",
+            "/nyheter/page/2/",
+            "/nyheter/?page=2",
+            "/api/nyheter?offset=20",
+        ] {
+            assert_eq!(priority(&page(path)), frontier::LISTING, "{}", path);
+        }
+        assert_eq!(priority("https://example.com/artikel/"), frontier::LISTING);
+    }
+
+    fn podd_meta(meta_url: &str, title: &str) -> Item {
+        Item::PoddMeta {
+            fetch: FetchInfo {
+                url: meta_url.to_string(),
+                final_url: meta_url.to_string(),
+                status: 200,
+                content_type: None,
+                fetched_at: "2024-03-02T10:00:00Z".parse().unwrap(),
+            },
+            meta_url: meta_url.to_string(),
+            title: title.to_string(),
+            release_date: "1 mars 2024".to_string(),
+            description: None,
+            mp3_url: "https://traffic.libsyn.com/omvarlden/avsnitt-1.mp3".to_string(),
+        }
+    }
+
+    #[test]
+    fn refetched_episodes_keep_their_links() {
+        let output = tempfile::tempdir().unwrap();
+        let spider = OmvarldenSpider::new(OmvarldenSpiderOptions {
+            output_path: output.path().to_path_buf(),
+            ..Default::default()
+        })
+        .unwrap();
+        let meta_url = format!("{}/avsnitt-1", OmvarldenSpider::PODD_META_URL);
+        let key = podd_meta_key(&Url::parse(&meta_url).unwrap());
+        spider
+            .process_item(&meta_url, podd_meta(&meta_url, "Avsnitt 1"))
+            .unwrap();
+        // as written by `link`
+        let mut document = spider.storage.get_document(&key).unwrap().unwrap();
+        let articles = serde_json::json!([{ "url": page("/poddar/avsnitt-1/") }]);
+        document["articles"] = articles.clone();
+        spider.storage.put_document(&key, &document).unwrap();
+
+        spider
+            .process_item(&meta_url, podd_meta(&meta_url, "Avsnitt 1: Vatten"))
+            .unwrap();
+        let document = spider.storage.get_document(&key).unwrap().unwrap();
+        assert_eq!(document["title"], "Avsnitt 1: Vatten");
+        assert_eq!(document["articles"], articles);
+    }
+
+    #[tokio::test]
+    async fn processes_on_a_current_thread_runtime() {
+        let output = tempfile::tempdir().unwrap();
+        let spider = OmvarldenSpider::new(OmvarldenSpiderOptions {
+            output_path: output.path().to_path_buf(),
+            ..Default::default()
+        })
+        .unwrap();
+        let meta_url = format!("{}/avsnitt-1", OmvarldenSpider::PODD_META_URL);
+        webcrawler::Spider::process(&spider, meta_url.clone(), podd_meta(&meta_url, "Avsnitt 1"))
+            .await
+            .unwrap();
+        let key = podd_meta_key(&Url::parse(&meta_url).unwrap());
+        assert!(spider.storage.exists(&key).unwrap());
+    }
+
+    #[test]
+    fn audio_last() {
+        assert_eq!(
+            priority("https://traffic.libsyn.com/omvarlden/avsnitt-12.mp3"),
+            frontier::AUDIO
+        );
+    }
+}
@@ -0,0 +1,336 @@
+//! Mapping between page URLs and the keys (relative paths) they are stored under.
+//!
+//! The mapping is collision free and can be reversed with [`page_url`]:
+//!
+//! - `/a/b` is stored as `a/b.html.gz`.
+//! - `/a/b/` (and `/`) is stored as `a/b/index.html.gz`.
+//! - The query string is appended to the file name after a `#`:
+//!   `/sok?q=x` is stored as `sok#q=x.html.gz`. Empty query strings are
+//!   dropped.
+//! - A page literally named `index` gets a leading `#`: `/a/index` is stored
+//!   as `a/#index.html.gz`.
+//! - Directories that are empty or end with `.html.gz` get a trailing `#`, so
+//!   they can't clash with a page: `/a//b.html.gz/c` is stored as
+//!   `a/#/b.html.gz#/c.html.gz`.
+//!
+//! `#` can't occur unencoded in the path or query of a URL, so the escapes
+//! never clash with a name taken from a URL.
+//!
+//! All keys are passed through [`sanitize_key`] so that they are usable as
+//! relative paths on common filesystems and can't point outside the output
+//! directory. Sanitizing percent-encodes `%` along with the characters it
+//! escapes, `/` in the query among them, so it is reversed by
+//! percent-decoding. Only keys with components shortened by sanitizing don't
+//! map back to their URL.
+
+use std::path::{Path, PathBuf};
+
+use reqwest::Url;
+use sha2::{Digest, Sha256};
+
+use crate::files::hex;
+use crate::omvarlden::OmvarldenSpider;
+
+const PAGE_SUFFIX: &str = ".html.gz";
+const INDEX: &str = "index";
+/// Longest path component kept as is, leaving room below the usual limit of
+/// 255 bytes for temporary file suffixes.
+const MAX_COMPONENT_BYTES: usize = 200;
+/// Characters not allowed in file names on Windows, besides control characters.
+const RESERVED_CHARS: &[char] = &['\\', ':', '*', '?', '"', '<', '>', '|'];
+/// File names reserved on Windows, with or without an extension.
+const RESERVED_NAMES: [&str; 22] = [
+    "con", "prn", "aux", "nul", "com1", "com2", "com3", "com4", "com5", "com6", "com7", "com8",
+    "com9", "lpt1", "lpt2", "lpt3", "lpt4", "lpt5", "lpt6", "lpt7", "lpt8", "lpt9",
+];
+
+/// The key the page at `url` is stored under.
+pub fn page_key(url: &Url) -> String {
+    let path = url.path().strip_prefix('/').unwrap_or(url.path());
+    let (dirs, name) = match path.rsplit_once('/') {
+        Some((dirs, name)) => (Some(dirs), name),
+        None => (None, path),
+    };
+    let mut key = String::new();
+    for dir in dirs.into_iter().flat_map(|dirs| dirs.split('/')) {
+        let mut dir = dir.to_string();
+        if dir.is_empty() || dir.ends_with(PAGE_SUFFIX) {
+            dir.push('#');
+        }
+        key.push_str(&sanitize_component(&dir));
+        key.push('/');
+    }
+    let mut file = match name {
+        "" => INDEX.to_string(),
+        INDEX => format!("#{}", INDEX),
+        name => name.to_string(),
+    };
+    if let Some(query) = url.query().filter(|query| !query.is_empty()) {
+        // may contain '/', which sanitizing the component escapes
+        file.push('#');
+        file.push_str(query);
+    }
+    file.push_str(PAGE_SUFFIX);
+    key.push_str(&sanitize_component(&file));
+    key
+}
+
+/// The URL of the page stored under `key`, the reverse of [`page_key`].
+pub fn page_url(key: &str) -> Option<Url> {
+    let key = key.strip_suffix(PAGE_SUFFIX)?;
+    let (dirs, stem) = match key.rsplit_once('/') {
+        Some((dirs, stem)) => (Some(dirs), stem),
+        None => (None, key),
+    };
+    let mut path = String::from("/");
+    for dir in dirs.into_iter().flat_map(|dirs| dirs.split('/')) {
+        path.push_str(&percent_decode(dir.strip_suffix('#').unwrap_or(dir))?);
+        path.push('/');
+    }
+    let (name, query) = match stem.strip_prefix('#') {
+        Some(literal) => split_query(literal),
+        None => match split_query(stem) {
+            (INDEX, query) => ("", query),
+            (name, query) => (name, query),
+        },
+    };
+    path.push_str(&percent_decode(name)?);
+    let mut url = format!("{}{}", OmvarldenSpider::BASE_URL, path);
+    if let Some(query) = query {
+        url.push('?');
+        url.push_str(&percent_decode(query)?);
+    }
+    Url::parse(&url).ok()
+}
+
+fn split_query(stem: &str) -> (&str, Option<&str>) {
+    match stem.split_once('#') {
+        Some((name, query)) => (name, Some(query)),
+        None => (stem, None),
+    }
+}
+
+/// Undo the escapes of [`sanitize_component`], `None` for a shortened component.
+fn percent_decode(sanitized: &str) -> Option<String> {
+    let mut decoded = Vec::with_capacity(sanitized.len());
+    let mut bytes = sanitized.bytes();
+    while let Some(b) = bytes.next() {
+        if b == b'%' {
+            let hex = [bytes.next()?, bytes.next()?];
+            let hex = std::str::from_utf8(&hex).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+        } else {
+            decoded.push(b);
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/// Make `key` safe to use as a relative path.
+///
+/// Each `/`-separated component is changed so that it
+///
+/// - is not empty, `.` or `..` (they become `%`, `%2E` and `%2E%2E`),
+/// - has no control or reserved characters, which are percent-encoded along
+///   with `%` itself,
+/// - doesn't end with `.` or a space and isn't a reserved Windows name like `con`,
+/// - is at most 200 bytes, longer components are cut and get `%~` and a hash
+///   of the original component, keeping the extension.
+///
+/// Different keys give different sanitized keys, barring hash collisions of
+/// shortened components. So sanitizing a sanitized key changes it again, its
+/// `%`s are escaped, but [`key_path`] takes it as it is.
+pub fn sanitize_key(key: &str) -> String {
+    key.split('/')
+        .map(sanitize_component)
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// The path of the sanitized `key` below `output_path`, which it never
+/// escapes. Components that aren't safe, which sanitized keys never have, are
+/// sanitized.
+pub fn key_path(output_path: &Path, key: &str) -> PathBuf {
+    let mut path = output_path.to_path_buf();
+    for component in key.split('/') {
+        if is_safe(component) {
+            path.push(component);
+        } else {
+            path.push(sanitize_component(component));
+        }
+    }
+    path
+}
+
+/// Whether `component` is left as it is by [`key_path`], true for every
+/// sanitized component.
+fn is_safe(component: &str) -> bool {
+    !matches!(component, "" | "." | "..")
+        && component.len() <= MAX_COMPONENT_BYTES
+        && !component
+            .chars()
+            .any(|c| c == '/' || c.is_control() || RESERVED_CHARS.contains(&c))
+        && !component.ends_with(['.', ' '])
+        && !RESERVED_NAMES.contains(&stem(component).to_ascii_lowercase().as_str())
+}
+
+/// The part of `component` before its extension.
+fn stem(component: &str) -> &str {
+    component.split('.').next().unwrap_or_default()
+}
+
+fn sanitize_component(component: &str) -> String {
+    match component {
+        "" => return "%".to_string(),
+        "." => return "%2E".to_string(),
+        ".." => return "%2E%2E".to_string(),
+        _ => {}
+    }
+    let mut sanitized = String::with_capacity(component.len());
+    for c in component.chars() {
+        if matches!(c, '%' | '/') || c.is_control() || RESERVED_CHARS.contains(&c) {
+            percent_encode(&mut sanitized, c);
+        } else {
+            sanitized.push(c);
+        }
+    }
+    if let Some(last) = sanitized.chars().last().filter(|c| matches!(c, '.' | ' ')) {
+        sanitized.pop();
+        percent_encode(&mut sanitized, last);
+    }
+    if RESERVED_NAMES.contains(&stem(&sanitized).to_ascii_lowercase().as_str()) {
+        let first = sanitized.remove(0);
+        let mut escaped = String::new();
+        percent_encode(&mut escaped, first);
+        sanitized.insert_str(0, &escaped);
+    }
+    if sanitized.len() > MAX_COMPONENT_BYTES {
+        sanitized = shorten(&sanitized);
+    }
+    sanitized
+}
+
+fn percent_encode(out: &mut String, c: char) {
+    let mut buf = [0; 4];
+    for b in c.encode_utf8(&mut buf).bytes() {
+        out.push_str(&format!("%{:02X}", b));
+    }
+}
+
+/// Cut `component` to fit, keeping the extension and adding a hash of the
+/// whole. The hash follows `%~`, which no other sanitized component contains.
+fn shorten(component: &str) -> String {
+    let hash = hex(&Sha256::digest(component.as_bytes()));
+    let hash = &hash[..16];
+    // the extension is everything from the first '.' in the last 16 bytes
+    let mut tail_start = component.len() - 16;
+    while !component.is_char_boundary(tail_start) {
+        tail_start += 1;
+    }
+    let extension = component[tail_start..]
+        .find('.')
+        .map(|i| &component[tail_start + i..])
+        .unwrap_or_default();
+    let mut prefix_len = MAX_COMPONENT_BYTES - extension.len() - hash.len() - 2;
+    while !component.is_char_boundary(prefix_len) {
+        prefix_len -= 1;
+    }
+    format!("{}%~{}{}", &component[..prefix_len], hash, extension)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    /// Keys with the characters sanitizing cares about.
+    fn key() -> impl Strategy<Value = String> {
+        let component = prop_oneof![
+            Just(String::new()),
+            Just(".".to_string()),
+            Just("..".to_string()),
+            Just("con".to_string()),
+            "[a-z%#.: /\\\\?\\x00-\\x1få]{0,12}",
+            "[a-z.%]{190,260}",
+        ];
+        prop::collection::vec(component, 1..5).prop_map(|components| components.join("/"))
+    }
+
+    proptest! {
+        #[test]
+        fn key_path_stays_inside_output_path(key in key()) {
+            let output_path = Path::new("/output");
+            for path in [key_path(output_path, &key), key_path(output_path, &sanitize_key(&key))] {
+                prop_assert!(path.starts_with(output_path));
+                prop_assert!(path.components().count() > output_path.components().count());
+                prop_assert!(path
+                    .components()
+                    .all(|component| matches!(component, std::path::Component::RootDir | std::path::Component::Normal(_))));
+            }
+        }
+
+        #[test]
+        fn components_are_short(key in key()) {
+            for component in sanitize_key(&key).split('/') {
+                prop_assert!(component.len() <= MAX_COMPONENT_BYTES, "{}", component);
+            }
+        }
+
+        #[test]
+        fn sanitized_keys_are_used_as_they_are(key in key()) {
+            let sanitized = sanitize_key(&key);
+            for component in sanitized.split('/') {
+                prop_assert!(is_safe(component), "{}", component);
+            }
+            let output_path = Path::new("/output");
+            prop_assert_eq!(key_path(output_path, &sanitized), output_path.join(&sanitized));
+        }
+
+        #[test]
+        fn different_keys_stay_different(a in key(), b in key()) {
+            prop_assume!(a != b);
+            prop_assert_ne!(sanitize_key(&a), sanitize_key(&b));
+        }
+
+        #[test]
+        fn page_keys_map_back(path in "(/[a-z0-9%.:-]{0,8}){1,4}", query in "[a-z0-9=&%/:.]{0,12}") {
+            let mut url = Url::parse(&format!("{}{}", OmvarldenSpider::BASE_URL, path)).unwrap();
+            url.set_query(Some(query.as_str()).filter(|query| !query.is_empty()));
+            let key = page_key(&url);
+            prop_assert_eq!(page_url(&key), Some(url));
+        }
+    }
+
+    #[test]
+    fn escapes_do_not_collide() {
+        for (a, b) in [
+            (".", "%2E"),
+            ("..", "%2E%2E"),
+            ("con", "%63on"),
+            ("a:b", "a%3Ab"),
+            ("", "#"),
+        ] {
+            assert_ne!(sanitize_key(a), sanitize_key(b), "{} and {}", a, b);
+        }
+    }
+
+    #[test]
+    fn page_keys() {
+        for (url, key) in [
+            ("/", "index.html.gz"),
+            ("/a/b", "a/b.html.gz"),
+            ("/a/b/", "a/b/index.html.gz"),
+            ("/a/index", "a/#index.html.gz"),
+            ("/a//b.html.gz/c", "a/#/b.html.gz#/c.html.gz"),
+            ("/sok?q=a/b%20c", "sok#q=a%2Fb%2520c.html.gz"),
+            ("/con", "%63on.html.gz"),
+        ] {
+            let url = Url::parse(OmvarldenSpider::BASE_URL)
+                .unwrap()
+                .join(url)
+                .unwrap();
+            assert_eq!(page_key(&url), key);
+            assert_eq!(page_url(key), Some(url));
+        }
+    }
+}
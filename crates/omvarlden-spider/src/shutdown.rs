@@ -0,0 +1,78 @@
+//! Stopping a crawl early.
+//!
+//! A [`Shutdown`] is shared by the spider and the program running the crawl.
+//! Once triggered, [`Shutdown::wait`] completes, which is used as the signal
+//! that stops `webcrawler` from scheduling new fetches and makes it save its
+//! state.
+
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::Notify;
+
+/// Why a crawl was stopped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShutdownReason {
+    /// The process got a signal, like `SIGTERM`.
+    Signal(&'static str),
+    /// A budget of the crawl, like `pages`, is used up.
+    Budget(&'static str),
+}
+
+impl fmt::Display for ShutdownReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Signal(signal) => f.write_fmt(format_args!("received {}", signal)),
+            Self::Budget(budget) => f.write_fmt(format_args!("used up the {} budget", budget)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Shutdown {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    reason: Mutex<Option<ShutdownReason>>,
+    notify: Notify,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stop the crawl. Only the first reason is kept.
+    pub fn trigger(&self, reason: ShutdownReason) {
+        let mut current = self.inner.reason.lock().unwrap();
+        if current.is_none() {
+            tracing::warn!("shutting down: {}", reason);
+            *current = Some(reason);
+            self.inner.notify.notify_waiters();
+        }
+    }
+
+    pub fn reason(&self) -> Option<ShutdownReason> {
+        self.inner.reason.lock().unwrap().clone()
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        self.inner.reason.lock().unwrap().is_some()
+    }
+
+    /// Wait until the shutdown is triggered.
+    pub async fn wait(&self) {
+        loop {
+            // registered before checking, so a trigger in between isn't missed
+            let notified = self.inner.notify.notified();
+            if self.is_triggered() {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
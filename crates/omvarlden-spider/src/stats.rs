@@ -0,0 +1,301 @@
+//! Statistics of a crawl, collected while crawling or computed afterwards
+//! from the crawl manifest.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt,
+    path::Path,
+    sync::Mutex,
+    time::Instant,
+};
+
+use chrono::{DateTime, Utc};
+use reqwest::Url;
+
+use crate::failures::{read_failures, FAILURES_FILE};
+use crate::files::write_json;
+use crate::manifest::{read_manifest, MANIFEST_FILE, UPDATE_KIND};
+use crate::Error;
+
+pub const STATS_FILE: &str = "crawl_stats.json";
+/// How many failing URL prefixes a summary lists.
+const TOP_FAILING_PREFIXES: usize = 10;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CrawlSummary {
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub duration_secs: f64,
+    /// Responses received, by HTTP status.
+    pub fetches_by_status: BTreeMap<u16, usize>,
+    pub items_by_kind: BTreeMap<String, usize>,
+    pub errors_by_code: BTreeMap<String, usize>,
+    /// URL prefixes (host and first path segment) with the most errors.
+    pub failing_prefixes: Vec<(String, usize)>,
+    pub bytes_downloaded: u64,
+}
+
+impl CrawlSummary {
+    pub fn fetches(&self) -> usize {
+        self.fetches_by_status.values().sum()
+    }
+
+    pub fn errors(&self) -> usize {
+        self.errors_by_code.values().sum()
+    }
+
+    pub fn fetches_per_sec(&self) -> f64 {
+        per_sec(self.fetches() as f64, self.duration_secs)
+    }
+
+    pub fn bytes_per_sec(&self) -> f64 {
+        per_sec(self.bytes_downloaded as f64, self.duration_secs)
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let fetches_by_status: serde_json::Map<_, _> = self
+            .fetches_by_status
+            .iter()
+            .map(|(status, count)| (status.to_string(), (*count).into()))
+            .collect();
+        serde_json::json!({
+            "started_at": self.started_at.map(|t| t.to_rfc3339()),
+            "finished_at": self.finished_at.map(|t| t.to_rfc3339()),
+            "duration_secs": self.duration_secs,
+            "fetches": self.fetches(),
+            "fetches_by_status": fetches_by_status,
+            "items_by_kind": self.items_by_kind,
+            "errors": self.errors(),
+            "errors_by_code": self.errors_by_code,
+            "failing_prefixes": self.failing_prefixes
+                .iter()
+                .map(|(prefix, count)| serde_json::json!({ "prefix": prefix, "errors": count }))
+                .collect::<Vec<_>>(),
+            "bytes_downloaded": self.bytes_downloaded,
+            "fetches_per_sec": self.fetches_per_sec(),
+            "bytes_per_sec": self.bytes_per_sec(),
+        })
+    }
+
+    /// Write the summary as JSON to `path`.
+    pub fn write(&self, path: &Path) -> Result<(), Error> {
+        write_json(path, &self.to_json())
+    }
+}
+
+impl fmt::Display for CrawlSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "duration:   {:.0} s ({:.2} fetches/s, {}/s)",
+            self.duration_secs,
+            self.fetches_per_sec(),
+            human_bytes(self.bytes_per_sec() as u64)
+        )?;
+        writeln!(f, "fetched:    {} urls", self.fetches())?;
+        for (status, count) in &self.fetches_by_status {
+            writeln!(f, "  {:<24} {}", status, count)?;
+        }
+        writeln!(f, "downloaded: {}", human_bytes(self.bytes_downloaded))?;
+        writeln!(
+            f,
+            "items:      {}",
+            self.items_by_kind.values().sum::<usize>()
+        )?;
+        for (kind, count) in &self.items_by_kind {
+            writeln!(f, "  {:<24} {}", kind, count)?;
+        }
+        writeln!(f, "errors:     {}", self.errors())?;
+        for (code, count) in &self.errors_by_code {
+            writeln!(f, "  {:<24} {}", code, count)?;
+        }
+        if !self.failing_prefixes.is_empty() {
+            writeln!(f, "most failing:")?;
+            for (prefix, count) in &self.failing_prefixes {
+                writeln!(f, "  {:<48} {}", prefix, count)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Counters updated while crawling.
+#[derive(Debug)]
+pub struct CrawlStats {
+    started: Instant,
+    started_at: DateTime<Utc>,
+    inner: Mutex<Counts>,
+}
+
+#[derive(Debug, Default)]
+struct Counts {
+    fetches_by_status: BTreeMap<u16, usize>,
+    items_by_kind: BTreeMap<String, usize>,
+    errors_by_code: BTreeMap<String, usize>,
+    errors_by_prefix: BTreeMap<String, usize>,
+    bytes_downloaded: u64,
+}
+
+impl Default for CrawlStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CrawlStats {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            started_at: Utc::now(),
+            inner: Mutex::new(Counts::default()),
+        }
+    }
+
+    pub fn record_fetch(&self, status: u16) {
+        *self
+            .inner
+            .lock()
+            .unwrap()
+            .fetches_by_status
+            .entry(status)
+            .or_default() += 1;
+    }
+
+    pub fn record_bytes(&self, bytes: usize) {
+        self.inner.lock().unwrap().bytes_downloaded += bytes as u64;
+    }
+
+    pub fn record_item(&self, kind: &str) {
+        *self
+            .inner
+            .lock()
+            .unwrap()
+            .items_by_kind
+            .entry(kind.to_string())
+            .or_default() += 1;
+    }
+
+    pub fn record_error(&self, url: &str, error: &Error) {
+        let mut inner = self.inner.lock().unwrap();
+        *inner
+            .errors_by_code
+            .entry(error.code().to_string())
+            .or_default() += 1;
+        *inner.errors_by_prefix.entry(url_prefix(url)).or_default() += 1;
+    }
+
+    pub fn summary(&self) -> CrawlSummary {
+        let inner = self.inner.lock().unwrap();
+        CrawlSummary {
+            started_at: Some(self.started_at),
+            finished_at: Some(Utc::now()),
+            duration_secs: self.started.elapsed().as_secs_f64(),
+            fetches_by_status: inner.fetches_by_status.clone(),
+            items_by_kind: inner.items_by_kind.clone(),
+            errors_by_code: inner.errors_by_code.clone(),
+            failing_prefixes: top(&inner.errors_by_prefix, TOP_FAILING_PREFIXES),
+            bytes_downloaded: inner.bytes_downloaded,
+        }
+    }
+}
+
+/// Compute a summary from the crawl manifest and failures file in `output_path`.
+///
+/// The manifest only records processed items, so a fetched page counts once
+/// per item taken from it and failed fetches are not included in the fetches.
+/// Pages skipped without storing anything aren't items. Every failed attempt
+/// in the failures file counts as an error.
+pub fn summarize_output(output_path: &Path) -> Result<CrawlSummary, Error> {
+    let mut summary = CrawlSummary::default();
+    // items from the same page share the fetch, count it once
+    let mut fetches = HashSet::new();
+    for entry in read_manifest(&output_path.join(MANIFEST_FILE))? {
+        if entry["kind"] == UPDATE_KIND {
+            continue;
+        }
+        let fetched_at = entry["fetched_at"]
+            .as_str()
+            .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+            .map(|t| t.with_timezone(&Utc));
+        if let Some(fetched_at) = fetched_at {
+            summary.started_at = Some(summary.started_at.map_or(fetched_at, |t| t.min(fetched_at)));
+            summary.finished_at = Some(
+                summary
+                    .finished_at
+                    .map_or(fetched_at, |t| t.max(fetched_at)),
+            );
+        }
+        if entry["skipped"].is_null() {
+            let kind = entry["kind"].as_str().unwrap_or("unknown");
+            *summary.items_by_kind.entry(kind.to_string()).or_default() += 1;
+        }
+        let url = entry["url"].as_str().unwrap_or_default();
+        if fetches.insert((url.to_string(), entry["fetched_at"].to_string())) {
+            let status = entry["status"].as_u64().unwrap_or_default() as u16;
+            *summary.fetches_by_status.entry(status).or_default() += 1;
+            summary.bytes_downloaded += entry["size"].as_u64().unwrap_or_default();
+        }
+    }
+    let mut errors_by_prefix = BTreeMap::new();
+    for failure in read_failures(&output_path.join(FAILURES_FILE))? {
+        if failure["resolved_at"].is_string() {
+            continue;
+        }
+        let code = failure["error"]["code"].as_str().unwrap_or("unknown");
+        *summary.errors_by_code.entry(code.to_string()).or_default() += 1;
+        let url = failure["url"].as_str().unwrap_or_default();
+        *errors_by_prefix.entry(url_prefix(url)).or_default() += 1;
+    }
+    summary.failing_prefixes = top(&errors_by_prefix, TOP_FAILING_PREFIXES);
+    if let (Some(started_at), Some(finished_at)) = (summary.started_at, summary.finished_at) {
+        summary.duration_secs = (finished_at - started_at).num_milliseconds() as f64 / 1000.0;
+    }
+    Ok(summary)
+}
+
+/// The host and first path segment of `url`.
+fn url_prefix(url: &str) -> String {
+    match Url::parse(url) {
+        Ok(url) => {
+            let first = url
+                .path_segments()
+                .and_then(|mut segments| segments.next())
+                .unwrap_or_default();
+            format!("{}/{}", url.host_str().unwrap_or_default(), first)
+        }
+        Err(_) => url.to_string(),
+    }
+}
+
+fn top(counts: &BTreeMap<String, usize>, n: usize) -> Vec<(String, usize)> {
+    let mut counts: Vec<_> = counts
+        .iter()
+        .map(|(key, count)| (key.clone(), *count))
+        .collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    counts.truncate(n);
+    counts
+}
+
+fn per_sec(value: f64, secs: f64) -> f64 {
+    if secs > 0.0 {
+        value / secs
+    } else {
+        0.0
+    }
+}
+
+fn human_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
@@ -0,0 +1,158 @@
+//! Where the spider writes its output.
+//!
+//! Everything is stored under a key, a relative path with `/` separators such as
+//! `poddar/avsnitt-1.html.gz` or `podd_meta/avsnitt-1.json`. Blobs are fetched
+//! content (compressed pages, episodes), documents are JSON metadata that may be
+//! read back and updated.
+
+use std::path::{Path, PathBuf};
+
+mod bundle;
+mod dry_run;
+mod fs;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+pub use self::bundle::{is_zip_archive, TarStorage, ZipStorage, TAR_FILE};
+pub use self::dry_run::{DryRunReport, DryRunReporter, DryRunStorage};
+pub use self::fs::{ContentAddressedStorage, FsStorage};
+#[cfg(feature = "sqlite")]
+pub use self::sqlite::SqliteStorage;
+
+use crate::article::Article;
+use crate::item::FetchInfo;
+use crate::Error;
+
+pub trait Storage: Send + Sync {
+    /// Store content fetched from `url` under `key`, replacing any previous content.
+    fn put_blob(&self, key: &str, url: &str, data: &[u8]) -> Result<(), Error>;
+
+    /// Like [`Storage::put_blob`], for content already hashed, `sha256` being
+    /// the hex SHA-256 of `data`.
+    fn put_hashed_blob(
+        &self,
+        key: &str,
+        url: &str,
+        data: &[u8],
+        _sha256: &str,
+    ) -> Result<(), Error> {
+        self.put_blob(key, url, data)
+    }
+
+    /// Store a JSON document under `key`, replacing any previous document.
+    fn put_document(&self, key: &str, document: &serde_json::Value) -> Result<(), Error>;
+
+    fn get_document(&self, key: &str) -> Result<Option<serde_json::Value>, Error>;
+
+    fn exists(&self, key: &str) -> Result<bool, Error>;
+
+    /// All keys starting with `prefix`.
+    fn list(&self, prefix: &str) -> Result<Vec<String>, Error>;
+
+    /// Store a fetched page, gzip compressed, with the article fields extracted from it.
+    fn put_page(
+        &self,
+        key: &str,
+        fetch: &FetchInfo,
+        compressed_html: &[u8],
+        _article: &Article,
+    ) -> Result<(), Error> {
+        self.put_blob(key, &fetch.url, compressed_html)
+    }
+
+    /// Record that a url was fetched, whether or not anything is stored for it.
+    fn record_fetch(&self, _fetch: &FetchInfo) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// The file holding `key`, for storages that keep one file per key.
+    fn local_path(&self, _key: &str) -> Option<PathBuf> {
+        None
+    }
+
+    /// Flush and close the storage, called once after the crawl.
+    fn finish(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StorageKind {
+    /// One file per key below the output directory.
+    #[default]
+    Tree,
+    /// Like `Tree`, with identical content stored once, see [`crate::cas`].
+    ContentAddressed,
+    /// A single SQLite database, `omvarlden.sqlite` in the output directory.
+    #[cfg(feature = "sqlite")]
+    Sqlite,
+    /// A single tar archive, `omvarlden.tar` in the output directory, appended
+    /// to by every crawl.
+    Tar,
+    /// A zip archive per crawl, `omvarlden-<start time>.zip` in the output
+    /// directory.
+    Zip,
+}
+
+/// Fail for an output directory written by a storage keeping everything in
+/// one file, where `what` reads the files of the tree layout and would find
+/// nothing.
+pub(crate) fn check_tree_layout(output_path: &Path, what: &'static str) -> Result<(), Error> {
+    if output_path.join("podd_meta").is_dir() {
+        return Ok(());
+    }
+    let single_file = std::fs::read_dir(output_path)
+        .into_iter()
+        .flatten()
+        .filter_map(Result::ok)
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .find(|name| name == "omvarlden.sqlite" || name == TAR_FILE || is_zip_archive(name));
+    match single_file {
+        Some(name) => Err(Error::Config {
+            what,
+            source: format!(
+                "'{}' is in '{}', only the tree and content-addressed storages are supported",
+                name,
+                output_path.display()
+            )
+            .into(),
+        }),
+        None => Ok(()),
+    }
+}
+
+impl StorageKind {
+    pub fn open(self, output_path: &Path) -> Result<Box<dyn Storage>, Error> {
+        let storage: Box<dyn Storage> = match self {
+            Self::Tree => Box::new(FsStorage::new(output_path)),
+            Self::ContentAddressed => Box::new(ContentAddressedStorage::open(output_path)?),
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite => Box::new(SqliteStorage::open(&output_path.join("omvarlden.sqlite"))?),
+            Self::Tar => Box::new(TarStorage::open(&output_path.join(TAR_FILE))?),
+            Self::Zip => Box::new(ZipStorage::open(output_path)?),
+        };
+        Ok(storage)
+    }
+
+    /// Where `key` is stored in `output_path`: a file, or a key in the file of
+    /// a single-file storage.
+    pub fn location(self, output_path: &Path, key: &str) -> String {
+        match self {
+            Self::Tree | Self::ContentAddressed => {
+                FsStorage::new(output_path).path(key).display().to_string()
+            }
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite => format!(
+                "{} in '{}'",
+                key,
+                output_path.join("omvarlden.sqlite").display()
+            ),
+            Self::Tar => format!("{} in '{}'", key, output_path.join(TAR_FILE).display()),
+            Self::Zip => format!(
+                "{} in a new '{}'",
+                key,
+                output_path.join("omvarlden-<start time>.zip").display()
+            ),
+        }
+    }
+}
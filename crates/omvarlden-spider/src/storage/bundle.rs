@@ -0,0 +1,511 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::SystemTime,
+};
+
+use chrono::{Datelike, Timelike, Utc};
+
+use super::Storage;
+use crate::Error;
+
+pub const TAR_FILE: &str = "omvarlden.tar";
+const ZIP_PREFIX: &str = "omvarlden-";
+const ZIP_SUFFIX: &str = ".zip";
+
+/// The position and size of the data of the last entry of every key in an
+/// archive.
+type Positions = HashMap<String, (u64, u64)>;
+
+/// Writes everything to a single tar archive.
+///
+/// Entries are appended to the archive as they arrive. A resumed crawl appends
+/// to the archive of the earlier crawls, and sees the keys stored in them. A
+/// document updated during the crawl is appended again, so the last entry of a
+/// key is the current one, which is also the one `tar -x` leaves.
+///
+/// A crawl stopping without [`Storage::finish`] leaves the archive without its
+/// end marker and possibly with a partial last entry, which the next crawl
+/// cuts off before appending.
+pub struct TarStorage {
+    path: PathBuf,
+    inner: Mutex<TarInner>,
+}
+
+struct TarInner {
+    builder: Option<tar::Builder<File>>,
+    /// The keys stored by earlier crawls and this one, documents are read back
+    /// from the archive.
+    entries: Positions,
+}
+
+impl TarStorage {
+    /// Append to the archive at `path`, creating it if missing.
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let (entries, end) = if path.exists() {
+            read_tar_index(path)?
+        } else {
+            (HashMap::new(), 0)
+        };
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)
+            .map_err(|error| Error::IoCreate {
+                path: path.to_path_buf(),
+                source: error,
+            })?;
+        // drops the end marker, or a partial entry
+        file.set_len(end)
+            .and_then(|()| file.seek(SeekFrom::Start(end)))
+            .map_err(|error| Error::IoWrite {
+                path: path.to_path_buf(),
+                source: error,
+            })?;
+        if !entries.is_empty() {
+            tracing::info!(
+                "appending to '{}', holding {} keys",
+                path.display(),
+                entries.len()
+            );
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            inner: Mutex::new(TarInner {
+                builder: Some(tar::Builder::new(file)),
+                entries,
+            }),
+        })
+    }
+
+    fn write_error(&self, error: io::Error) -> Error {
+        tracing::error!("failed writing '{}': {}", self.path.display(), error);
+        Error::IoWrite {
+            path: self.path.clone(),
+            source: error,
+        }
+    }
+
+    fn append(&self, inner: &mut TarInner, key: &str, data: &[u8]) -> Result<(), Error> {
+        let Some(builder) = inner.builder.as_mut() else {
+            return Err(self.write_error(io::Error::other("archive already finished")));
+        };
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+        );
+        header.set_cksum();
+        let size = data.len() as u64;
+        // the data ends the entry, padded to the block size
+        let end = builder
+            .append_data(&mut header, key, data)
+            .and_then(|()| builder.get_mut().stream_position())
+            .map_err(|error| self.write_error(error))?;
+        let start = end - size.div_ceil(512) * 512;
+        inner.entries.insert(key.to_string(), (start, size));
+        Ok(())
+    }
+}
+
+/// The keys in the archive at `path`, and where its last complete entry ends.
+fn read_tar_index(path: &Path) -> Result<(Positions, u64), Error> {
+    let read_error = |error| Error::IoRead {
+        path: path.to_path_buf(),
+        source: error,
+    };
+    let file = File::open(path).map_err(read_error)?;
+    let len = file.metadata().map_err(read_error)?.len();
+    let mut archive = tar::Archive::new(file);
+    let mut index = HashMap::new();
+    let mut end = 0;
+    for entry in archive.entries_with_seek().map_err(read_error)? {
+        // a partial header
+        let Ok(entry) = entry else {
+            break;
+        };
+        let start = entry.raw_file_position();
+        let size = entry.size();
+        let entry_end = start + size.div_ceil(512) * 512;
+        if entry_end > len {
+            break;
+        }
+        let Ok(key) = entry.path() else {
+            break;
+        };
+        index.insert(key.to_string_lossy().into_owned(), (start, size));
+        end = entry_end;
+    }
+    if len > end + 1024 {
+        tracing::warn!(
+            "'{}' ends with a partial entry, {} bytes are cut off",
+            path.display(),
+            len - end
+        );
+    }
+    Ok((index, end))
+}
+
+/// Read the document stored uncompressed at `position` in the archive at `path`.
+fn read_document(path: &Path, (start, size): (u64, u64)) -> Result<serde_json::Value, Error> {
+    let read_error = |error| Error::IoRead {
+        path: path.to_path_buf(),
+        source: error,
+    };
+    let mut file = File::open(path).map_err(read_error)?;
+    file.seek(SeekFrom::Start(start)).map_err(read_error)?;
+    let mut data = Vec::new();
+    file.take(size).read_to_end(&mut data).map_err(read_error)?;
+    serde_json::from_slice(&data).map_err(|error| Error::ParseJson {
+        path: path.to_path_buf(),
+        source: error,
+    })
+}
+
+impl Storage for TarStorage {
+    fn put_blob(&self, key: &str, _url: &str, data: &[u8]) -> Result<(), Error> {
+        let mut inner = self.inner.lock().unwrap();
+        self.append(&mut inner, key, data)
+    }
+
+    fn put_document(&self, key: &str, document: &serde_json::Value) -> Result<(), Error> {
+        let data = serde_json::to_vec(document).map_err(|error| Error::Serialization {
+            path: self.path.clone(),
+            source: error,
+        })?;
+        let mut inner = self.inner.lock().unwrap();
+        self.append(&mut inner, key, &data)
+    }
+
+    fn get_document(&self, key: &str) -> Result<Option<serde_json::Value>, Error> {
+        let Some(position) = self.inner.lock().unwrap().entries.get(key).copied() else {
+            return Ok(None);
+        };
+        read_document(&self.path, position).map(Some)
+    }
+
+    fn exists(&self, key: &str) -> Result<bool, Error> {
+        Ok(self.inner.lock().unwrap().entries.contains_key(key))
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<String>, Error> {
+        let inner = self.inner.lock().unwrap();
+        let keys: BTreeSet<&String> = inner
+            .entries
+            .keys()
+            .filter(|key| key.starts_with(prefix))
+            .collect();
+        Ok(keys.into_iter().cloned().collect())
+    }
+
+    fn finish(&self) -> Result<(), Error> {
+        let mut inner = self.inner.lock().unwrap();
+        let Some(builder) = inner.builder.take() else {
+            return Ok(());
+        };
+        builder
+            .into_inner()
+            .and_then(|file| file.sync_all())
+            .map_err(|error| self.write_error(error))?;
+        tracing::info!("finished '{}'", self.path.display());
+        Ok(())
+    }
+}
+
+/// Writes everything to a new zip archive in every crawl,
+/// `omvarlden-<start time>.zip`.
+///
+/// The index of a zip archive is written last, when it is finished, so
+/// appending to a finished archive would risk all of it if the crawl stops.
+/// Instead a resumed crawl writes a new archive, and sees the keys stored in
+/// the archives of the earlier crawls. Entries are written as they arrive,
+/// uncompressed since pages and episodes are compressed already. A document
+/// updated during the crawl is written again, so the current document of a
+/// key is its last entry in the latest archive holding it.
+///
+/// A crawl stopping without [`Storage::finish`] leaves an archive without
+/// index, whose entries `zip -FF` recovers. Later crawls skip it.
+///
+/// Documents aren't kept in memory, those of this crawl are read back from
+/// where their data was written, which works without the index.
+pub struct ZipStorage {
+    path: PathBuf,
+    inner: Mutex<ZipInner>,
+}
+
+struct ZipInner {
+    writer: Option<zip::ZipWriter<File>>,
+    /// The latest archive of an earlier crawl holding each key.
+    earlier: HashMap<String, PathBuf>,
+    /// The keys stored in this crawl.
+    written: Positions,
+}
+
+impl ZipStorage {
+    /// Start a new archive in `output_path`, after the ones already there.
+    pub fn open(output_path: &Path) -> Result<Self, Error> {
+        let mut earlier = HashMap::new();
+        for archive_path in zip_archives(output_path)? {
+            let archive = File::open(&archive_path)
+                .map_err(zip::result::ZipError::from)
+                .and_then(zip::ZipArchive::new);
+            match archive {
+                Ok(archive) => {
+                    for key in archive.file_names() {
+                        earlier.insert(key.to_string(), archive_path.clone());
+                    }
+                }
+                Err(error) => tracing::warn!(
+                    "skipping '{}', it isn't finished, recover it with `zip -FF`: {}",
+                    archive_path.display(),
+                    error
+                ),
+            }
+        }
+        let path = output_path.join(format!(
+            "{}{}{}",
+            ZIP_PREFIX,
+            Utc::now().format("%Y%m%dT%H%M%SZ"),
+            ZIP_SUFFIX
+        ));
+        let file = OpenOptions::new()
+            .create_new(true)
+            .write(true)
+            .open(&path)
+            .map_err(|error| Error::IoCreate {
+                path: path.clone(),
+                source: error,
+            })?;
+        tracing::info!(
+            "writing '{}', {} keys in earlier archives",
+            path.display(),
+            earlier.len()
+        );
+        Ok(Self {
+            path,
+            inner: Mutex::new(ZipInner {
+                writer: Some(zip::ZipWriter::new(file)),
+                earlier,
+                written: HashMap::new(),
+            }),
+        })
+    }
+
+    fn write_error(&self, error: io::Error) -> Error {
+        tracing::error!("failed writing '{}': {}", self.path.display(), error);
+        Error::IoWrite {
+            path: self.path.clone(),
+            source: error,
+        }
+    }
+
+    fn append(&self, inner: &mut ZipInner, key: &str, data: &[u8]) -> Result<(), Error> {
+        let Some(writer) = inner.writer.as_mut() else {
+            return Err(self.write_error(io::Error::other("archive already finished")));
+        };
+        let now = Utc::now();
+        let mut options = zip::write::FileOptions::default()
+            .compression_method(zip::CompressionMethod::Stored)
+            .unix_permissions(0o644)
+            .large_file(data.len() as u64 > u64::from(u32::MAX));
+        if let Ok(time) = zip::DateTime::from_date_and_time(
+            now.year() as u16,
+            now.month() as u8,
+            now.day() as u8,
+            now.hour() as u8,
+            now.minute() as u8,
+            now.second() as u8,
+        ) {
+            options = options.last_modified_time(time);
+        }
+        // without extra data, for the position of the data
+        let start = writer
+            .start_file_with_extra_data(key, options)
+            .and_then(|_| writer.end_extra_data())
+            .map_err(io::Error::from)
+            .and_then(|start| writer.write_all(data).map(|()| start))
+            .map_err(|error| self.write_error(error))?;
+        inner
+            .written
+            .insert(key.to_string(), (start, data.len() as u64));
+        Ok(())
+    }
+}
+
+/// The zip archives of earlier crawls in `output_path`, oldest first.
+fn zip_archives(output_path: &Path) -> Result<Vec<PathBuf>, Error> {
+    let read_error = |error| Error::IoRead {
+        path: output_path.to_path_buf(),
+        source: error,
+    };
+    let mut archives = Vec::new();
+    for entry in fs::read_dir(output_path).map_err(read_error)? {
+        let entry = entry.map_err(read_error)?;
+        let name = entry.file_name();
+        if is_zip_archive(&name.to_string_lossy()) {
+            archives.push(entry.path());
+        }
+    }
+    // named by start time
+    archives.sort();
+    Ok(archives)
+}
+
+/// Whether `file_name` is the name of an archive written by [`ZipStorage`].
+pub fn is_zip_archive(file_name: &str) -> bool {
+    file_name.starts_with(ZIP_PREFIX) && file_name.ends_with(ZIP_SUFFIX)
+}
+
+impl Storage for ZipStorage {
+    fn put_blob(&self, key: &str, _url: &str, data: &[u8]) -> Result<(), Error> {
+        let mut inner = self.inner.lock().unwrap();
+        self.append(&mut inner, key, data)
+    }
+
+    fn put_document(&self, key: &str, document: &serde_json::Value) -> Result<(), Error> {
+        let data = serde_json::to_vec(document).map_err(|error| Error::Serialization {
+            path: self.path.clone(),
+            source: error,
+        })?;
+        let mut inner = self.inner.lock().unwrap();
+        self.append(&mut inner, key, &data)
+    }
+
+    fn get_document(&self, key: &str) -> Result<Option<serde_json::Value>, Error> {
+        let archive_path = {
+            let inner = self.inner.lock().unwrap();
+            if let Some(position) = inner.written.get(key).copied() {
+                drop(inner);
+                return read_document(&self.path, position).map(Some);
+            }
+            match inner.earlier.get(key) {
+                Some(archive_path) => archive_path.clone(),
+                None => return Ok(None),
+            }
+        };
+        let read_error = |error| Error::IoRead {
+            path: archive_path.clone(),
+            source: error,
+        };
+        let mut data = Vec::new();
+        File::open(&archive_path)
+            .map_err(zip::result::ZipError::from)
+            .and_then(zip::ZipArchive::new)
+            .map_err(io::Error::from)
+            .and_then(|mut archive| {
+                archive
+                    .by_name(key)
+                    .map_err(io::Error::from)
+                    .and_then(|mut entry| entry.read_to_end(&mut data))
+            })
+            .map_err(read_error)?;
+        let document = serde_json::from_slice(&data).map_err(|error| Error::ParseJson {
+            path: archive_path.clone(),
+            source: error,
+        })?;
+        Ok(Some(document))
+    }
+
+    fn exists(&self, key: &str) -> Result<bool, Error> {
+        let inner = self.inner.lock().unwrap();
+        Ok(inner.written.contains_key(key) || inner.earlier.contains_key(key))
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<String>, Error> {
+        let inner = self.inner.lock().unwrap();
+        let keys: BTreeSet<&String> = inner
+            .written
+            .keys()
+            .chain(inner.earlier.keys())
+            .filter(|key| key.starts_with(prefix))
+            .collect();
+        Ok(keys.into_iter().cloned().collect())
+    }
+
+    fn finish(&self) -> Result<(), Error> {
+        let mut inner = self.inner.lock().unwrap();
+        let Some(mut writer) = inner.writer.take() else {
+            return Ok(());
+        };
+        writer
+            .finish()
+            .map_err(io::Error::from)
+            .and_then(|file| file.sync_all())
+            .map_err(|error| self.write_error(error))?;
+        tracing::info!("finished '{}'", self.path.display());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    /// A key too long for the name field of a tar header.
+    const LONG_KEY: &str = "podd_meta/ett-mycket-langt-namn-pa-ett-avsnitt-som-inte-far-plats-i-ett-tar-huvud-utan-ett-extra-block.json";
+
+    fn store(storage: &dyn Storage) {
+        storage.put_document("a.json", &json!({"v": 1})).unwrap();
+        storage.put_blob("a.mp3", "", &[7; 1000]).unwrap();
+        storage.put_document(LONG_KEY, &json!({"v": 2})).unwrap();
+        storage.put_document("a.json", &json!({"v": 3})).unwrap();
+    }
+
+    fn check(storage: &dyn Storage) {
+        assert_eq!(
+            storage.get_document("a.json").unwrap(),
+            Some(json!({"v": 3}))
+        );
+        assert_eq!(
+            storage.get_document(LONG_KEY).unwrap(),
+            Some(json!({"v": 2}))
+        );
+        assert_eq!(storage.get_document("b.json").unwrap(), None);
+        assert!(storage.exists("a.mp3").unwrap());
+        assert_eq!(storage.list("a.").unwrap(), ["a.json", "a.mp3"]);
+    }
+
+    #[test]
+    fn tar_documents_are_read_back() {
+        let output = tempfile::tempdir().unwrap();
+        let path = output.path().join(TAR_FILE);
+        let storage = TarStorage::open(&path).unwrap();
+        store(&storage);
+        check(&storage);
+        storage.finish().unwrap();
+
+        let resumed = TarStorage::open(&path).unwrap();
+        check(&resumed);
+        resumed.put_document(LONG_KEY, &json!({"v": 4})).unwrap();
+        assert_eq!(
+            resumed.get_document(LONG_KEY).unwrap(),
+            Some(json!({"v": 4}))
+        );
+    }
+
+    #[test]
+    fn zip_documents_are_read_back() {
+        let output = tempfile::tempdir().unwrap();
+        let storage = ZipStorage::open(output.path()).unwrap();
+        store(&storage);
+        check(&storage);
+        storage.finish().unwrap();
+        let archive = zip::ZipArchive::new(File::open(&storage.path).unwrap()).unwrap();
+        assert_eq!(archive.len(), 4);
+
+        // named by the second the crawl started
+        std::thread::sleep(std::time::Duration::from_secs(1));
+        let resumed = ZipStorage::open(output.path()).unwrap();
+        check(&resumed);
+    }
+}
@@ -0,0 +1,191 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use super::{FsStorage, Storage, StorageKind};
+use crate::Error;
+
+/// Plans what a storage would write, without writing anything.
+///
+/// Keys stored for two different URLs are collisions, keys already in the
+/// output directory are overwrites. Overwrites are only found for the storages
+/// with one file per key. Documents are kept in memory, so they can be read
+/// back and updated like in a real crawl.
+pub struct DryRunStorage {
+    kind: StorageKind,
+    output_path: std::path::PathBuf,
+    /// The output as it is, for the storages with one file per key.
+    existing: Option<FsStorage>,
+    plan: Arc<Mutex<Plan>>,
+}
+
+#[derive(Debug, Default)]
+struct Plan {
+    /// The URLs of every key planned, a document has none.
+    keys: BTreeMap<String, BTreeSet<String>>,
+    documents: HashMap<String, serde_json::Value>,
+    overwrites: BTreeSet<String>,
+}
+
+/// What a dry run would have written.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DryRunReport {
+    /// Keys that would be written.
+    pub writes: usize,
+    /// Keys that already exist in the output directory.
+    pub overwrites: Vec<String>,
+    /// Keys that more than one URL would be written to, with the URLs.
+    pub collisions: Vec<(String, Vec<String>)>,
+}
+
+impl DryRunStorage {
+    /// Plan writing to `kind` of storage in `output_path`.
+    pub fn new(kind: StorageKind, output_path: &Path) -> Self {
+        let existing = match kind {
+            StorageKind::Tree | StorageKind::ContentAddressed => Some(FsStorage::new(output_path)),
+            _ => None,
+        };
+        Self {
+            kind,
+            output_path: output_path.to_path_buf(),
+            existing,
+            plan: Arc::default(),
+        }
+    }
+
+    /// A handle for getting the report of the plan, after the storage is
+    /// handed to the spider.
+    pub fn reporter(&self) -> DryRunReporter {
+        DryRunReporter {
+            plan: self.plan.clone(),
+        }
+    }
+
+    fn plan(&self, key: &str, url: Option<&str>) -> Result<(), Error> {
+        let location = self.kind.location(&self.output_path, key);
+        let mut plan = self.plan.lock().unwrap();
+        let first = !plan.keys.contains_key(key);
+        let urls = plan.keys.entry(key.to_string()).or_default();
+        if let Some(url) = url {
+            if urls.insert(url.to_string()) && urls.len() > 1 {
+                tracing::warn!("collision: {} is written for {:?}", location, urls);
+            }
+        }
+        if first {
+            let exists = match &self.existing {
+                Some(existing) => existing.exists(key)?,
+                None => false,
+            };
+            if exists {
+                tracing::warn!("would overwrite {}", location);
+                plan.overwrites.insert(key.to_string());
+            } else {
+                tracing::info!("would write {}", location);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Storage for DryRunStorage {
+    fn put_blob(&self, key: &str, url: &str, _data: &[u8]) -> Result<(), Error> {
+        self.plan(key, Some(url))
+    }
+
+    fn put_document(&self, key: &str, document: &serde_json::Value) -> Result<(), Error> {
+        self.plan(key, None)?;
+        self.plan
+            .lock()
+            .unwrap()
+            .documents
+            .insert(key.to_string(), document.clone());
+        Ok(())
+    }
+
+    fn get_document(&self, key: &str) -> Result<Option<serde_json::Value>, Error> {
+        if let Some(document) = self.plan.lock().unwrap().documents.get(key) {
+            return Ok(Some(document.clone()));
+        }
+        match &self.existing {
+            Some(existing) => existing.get_document(key),
+            None => Ok(None),
+        }
+    }
+
+    fn exists(&self, key: &str) -> Result<bool, Error> {
+        if self.plan.lock().unwrap().keys.contains_key(key) {
+            return Ok(true);
+        }
+        match &self.existing {
+            Some(existing) => existing.exists(key),
+            None => Ok(false),
+        }
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<String>, Error> {
+        let mut keys: BTreeSet<String> = match &self.existing {
+            Some(existing) => existing.list(prefix)?.into_iter().collect(),
+            None => BTreeSet::new(),
+        };
+        let plan = self.plan.lock().unwrap();
+        keys.extend(
+            plan.keys
+                .keys()
+                .filter(|key| key.starts_with(prefix))
+                .cloned(),
+        );
+        Ok(keys.into_iter().collect())
+    }
+}
+
+#[derive(Clone)]
+pub struct DryRunReporter {
+    plan: Arc<Mutex<Plan>>,
+}
+
+impl DryRunReporter {
+    pub fn report(&self) -> DryRunReport {
+        let plan = self.plan.lock().unwrap();
+        DryRunReport {
+            writes: plan.keys.len(),
+            overwrites: plan.overwrites.iter().cloned().collect(),
+            collisions: plan
+                .keys
+                .iter()
+                .filter(|(_, urls)| urls.len() > 1)
+                .map(|(key, urls)| (key.clone(), urls.iter().cloned().collect()))
+                .collect(),
+        }
+    }
+}
+
+impl DryRunReport {
+    pub fn is_ok(&self) -> bool {
+        self.collisions.is_empty()
+    }
+}
+
+impl fmt::Display for DryRunReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (key, urls) in &self.collisions {
+            writeln!(f, "collision {}", key)?;
+            for url in urls {
+                writeln!(f, "  {}", url)?;
+            }
+        }
+        for key in &self.overwrites {
+            writeln!(f, "overwrite {}", key)?;
+        }
+        writeln!(
+            f,
+            "would write {} keys: {} new, {} overwritten, {} collisions",
+            self.writes,
+            self.writes - self.overwrites.len(),
+            self.overwrites.len(),
+            self.collisions.len()
+        )
+    }
+}
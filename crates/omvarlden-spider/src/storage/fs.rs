@@ -0,0 +1,156 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use sha2::{Digest, Sha256};
+
+use super::Storage;
+use crate::cas::{ContentStore, PutOutcome};
+use crate::files::{files_with_suffix, hex, read_json, write_atomic, write_json};
+use crate::paths;
+use crate::Error;
+
+/// One file per key below the output directory.
+#[derive(Debug, Clone)]
+pub struct FsStorage {
+    root: PathBuf,
+}
+
+impl FsStorage {
+    pub fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+        }
+    }
+
+    pub fn path(&self, key: &str) -> PathBuf {
+        paths::key_path(&self.root, key)
+    }
+
+    /// Create the parent directory of `key` and return its path.
+    fn prepare(&self, key: &str) -> Result<PathBuf, Error> {
+        let path = self.path(key);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|error| {
+                tracing::error!(
+                    "failed creating path='{}', error={}",
+                    parent.display(),
+                    error
+                );
+                Error::IoCreate {
+                    path: parent.to_path_buf(),
+                    source: error,
+                }
+            })?;
+        }
+        Ok(path)
+    }
+}
+
+impl Storage for FsStorage {
+    fn put_blob(&self, key: &str, url: &str, data: &[u8]) -> Result<(), Error> {
+        let path = self.prepare(key)?;
+        write_atomic(&path, data).map_err(|error| {
+            tracing::error!("{:#}, url={}", error, url);
+            error
+        })
+    }
+
+    fn put_document(&self, key: &str, document: &serde_json::Value) -> Result<(), Error> {
+        let path = self.prepare(key)?;
+        write_json(&path, document)
+    }
+
+    fn get_document(&self, key: &str) -> Result<Option<serde_json::Value>, Error> {
+        let path = self.path(key);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        read_json(&path).map(Some)
+    }
+
+    fn exists(&self, key: &str) -> Result<bool, Error> {
+        Ok(self.path(key).is_file())
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<String>, Error> {
+        // walk the deepest directory covering the prefix
+        let dir = match prefix.rfind('/') {
+            Some(i) => self.root.join(&prefix[..i]),
+            None => self.root.clone(),
+        };
+        Ok(files_with_suffix(&dir, "")
+            .into_iter()
+            .filter_map(|path| {
+                let key = path.strip_prefix(&self.root).ok()?.to_str()?;
+                let key = key.replace(std::path::MAIN_SEPARATOR, "/");
+                key.starts_with(prefix).then_some(key)
+            })
+            .collect())
+    }
+
+    fn local_path(&self, key: &str) -> Option<PathBuf> {
+        Some(self.path(key))
+    }
+}
+
+/// The tree layout of [`FsStorage`], with blobs stored in a [`ContentStore`].
+pub struct ContentAddressedStorage {
+    tree: FsStorage,
+    store: ContentStore,
+}
+
+impl ContentAddressedStorage {
+    pub fn open(root: &Path) -> Result<Self, Error> {
+        Ok(Self {
+            tree: FsStorage::new(root),
+            store: ContentStore::open(root)?,
+        })
+    }
+}
+
+impl Storage for ContentAddressedStorage {
+    fn put_blob(&self, key: &str, url: &str, data: &[u8]) -> Result<(), Error> {
+        self.put_hashed_blob(key, url, data, &hex(&Sha256::digest(data)))
+    }
+
+    fn put_hashed_blob(
+        &self,
+        key: &str,
+        url: &str,
+        data: &[u8],
+        sha256: &str,
+    ) -> Result<(), Error> {
+        let path = self.tree.prepare(key)?;
+        let stored = self.store.put(url, data, sha256, &path)?;
+        tracing::debug!(
+            sha256 = stored.sha256,
+            deduplicated = stored.deduplicated,
+            unchanged = stored.outcome == PutOutcome::Unchanged,
+            "stored '{}'",
+            path.display()
+        );
+        Ok(())
+    }
+
+    fn put_document(&self, key: &str, document: &serde_json::Value) -> Result<(), Error> {
+        self.tree.put_document(key, document)
+    }
+
+    fn get_document(&self, key: &str) -> Result<Option<serde_json::Value>, Error> {
+        self.tree.get_document(key)
+    }
+
+    fn exists(&self, key: &str) -> Result<bool, Error> {
+        self.tree.exists(key)
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<String>, Error> {
+        self.tree.list(prefix)
+    }
+
+    fn local_path(&self, key: &str) -> Option<PathBuf> {
+        self.tree.local_path(key)
+    }
+}
@@ -0,0 +1,303 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use rusqlite::{params, Connection, OptionalExtension};
+
+use super::Storage;
+use crate::article::Article;
+use crate::date::ParsedDate;
+use crate::item::FetchInfo;
+use crate::Error;
+
+/// Stores everything in a single SQLite database.
+///
+/// Besides the generic `blobs` and `documents` tables, fetches, pages, articles
+/// and podcast episodes get tables of their own, with full-text search over
+/// the articles in `articles_fts`:
+///
+/// ```sql
+/// SELECT a.url, a.title, a.published
+/// FROM articles_fts JOIN articles a ON a.page_key = articles_fts.page_key
+/// WHERE articles_fts MATCH 'klimat' AND a.section = 'nyheter'
+///   AND a.published BETWEEN '2022-01-01' AND '2023-01-01';
+/// ```
+pub struct SqliteStorage {
+    path: PathBuf,
+    connection: Mutex<Connection>,
+}
+
+const SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS blobs (
+    key TEXT PRIMARY KEY,
+    url TEXT NOT NULL,
+    data BLOB NOT NULL,
+    stored_at TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS documents (
+    key TEXT PRIMARY KEY,
+    json TEXT NOT NULL,
+    stored_at TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS fetches (
+    id INTEGER PRIMARY KEY,
+    url TEXT NOT NULL,
+    final_url TEXT NOT NULL,
+    status INTEGER NOT NULL,
+    content_type TEXT,
+    fetched_at TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS fetches_url ON fetches (url);
+CREATE TABLE IF NOT EXISTS pages (
+    key TEXT PRIMARY KEY,
+    url TEXT NOT NULL,
+    fetch_id INTEGER REFERENCES fetches (id),
+    html_gz BLOB NOT NULL
+);
+CREATE INDEX IF NOT EXISTS pages_url ON pages (url);
+CREATE TABLE IF NOT EXISTS articles (
+    page_key TEXT PRIMARY KEY REFERENCES pages (key),
+    url TEXT NOT NULL,
+    title TEXT,
+    section TEXT,
+    published TEXT,
+    published_raw TEXT,
+    lead TEXT,
+    text TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS articles_section_published ON articles (section, published);
+CREATE VIRTUAL TABLE IF NOT EXISTS articles_fts USING fts5 (
+    page_key UNINDEXED,
+    title,
+    lead,
+    text,
+    tokenize = 'unicode61 remove_diacritics 0'
+);
+CREATE TABLE IF NOT EXISTS episodes (
+    key TEXT PRIMARY KEY,
+    meta_url TEXT,
+    mp3_url TEXT,
+    title TEXT,
+    release_date TEXT,
+    release_date_raw TEXT,
+    description TEXT,
+    duration_secs REAL,
+    byte_size INTEGER
+);
+CREATE INDEX IF NOT EXISTS episodes_mp3_url ON episodes (mp3_url);
+"#;
+
+impl SqliteStorage {
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let sqlite_error = |error| Error::Sqlite {
+            path: path.to_path_buf(),
+            source: error,
+        };
+        let connection = Connection::open(path).map_err(sqlite_error)?;
+        connection
+            .execute_batch("PRAGMA journal_mode = WAL; PRAGMA foreign_keys = ON;")
+            .map_err(sqlite_error)?;
+        connection.execute_batch(SCHEMA).map_err(sqlite_error)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            connection: Mutex::new(connection),
+        })
+    }
+
+    fn sqlite_error(&self, error: rusqlite::Error) -> Error {
+        tracing::error!("sqlite error in '{}': {}", self.path.display(), error);
+        Error::Sqlite {
+            path: self.path.clone(),
+            source: error,
+        }
+    }
+
+    fn put_episode(
+        connection: &Connection,
+        key: &str,
+        episode: &serde_json::Value,
+    ) -> rusqlite::Result<()> {
+        connection.execute(
+            "INSERT OR REPLACE INTO episodes
+                (key, meta_url, mp3_url, title, release_date, release_date_raw, description,
+                 duration_secs, byte_size)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![
+                key,
+                episode["meta_url"].as_str(),
+                episode["mp3_url"].as_str(),
+                episode["title"].as_str(),
+                episode["release_date"].as_str(),
+                episode["release_date_raw"].as_str(),
+                episode["description"].as_str(),
+                episode["audio"]["duration_secs"].as_f64(),
+                episode["audio"]["byte_size"].as_i64(),
+            ],
+        )?;
+        Ok(())
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn put_blob(&self, key: &str, url: &str, data: &[u8]) -> Result<(), Error> {
+        self.connection
+            .lock()
+            .unwrap()
+            .execute(
+                "INSERT OR REPLACE INTO blobs (key, url, data, stored_at) VALUES (?1, ?2, ?3, ?4)",
+                params![key, url, data, chrono::Utc::now().to_rfc3339()],
+            )
+            .map_err(|error| self.sqlite_error(error))?;
+        Ok(())
+    }
+
+    /// Episode metadata (`podd_meta/`) is also kept in the `episodes` table.
+    fn put_document(&self, key: &str, document: &serde_json::Value) -> Result<(), Error> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection
+            .transaction()
+            .map_err(|error| self.sqlite_error(error))?;
+        transaction
+            .execute(
+                "INSERT OR REPLACE INTO documents (key, json, stored_at) VALUES (?1, ?2, ?3)",
+                params![key, document.to_string(), chrono::Utc::now().to_rfc3339()],
+            )
+            .map_err(|error| self.sqlite_error(error))?;
+        if key.starts_with("podd_meta/") {
+            Self::put_episode(&transaction, key, document)
+                .map_err(|error| self.sqlite_error(error))?;
+        }
+        transaction
+            .commit()
+            .map_err(|error| self.sqlite_error(error))
+    }
+
+    fn get_document(&self, key: &str) -> Result<Option<serde_json::Value>, Error> {
+        let json: Option<String> = self
+            .connection
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT json FROM documents WHERE key = ?1",
+                params![key],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(|error| self.sqlite_error(error))?;
+        json.map(|json| {
+            serde_json::from_str(&json).map_err(|error| Error::ParseJson {
+                path: self.path.join(key),
+                source: error,
+            })
+        })
+        .transpose()
+    }
+
+    fn exists(&self, key: &str) -> Result<bool, Error> {
+        self.connection
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT EXISTS (SELECT 1 FROM blobs WHERE key = ?1)
+                    OR EXISTS (SELECT 1 FROM documents WHERE key = ?1)
+                    OR EXISTS (SELECT 1 FROM pages WHERE key = ?1)",
+                params![key],
+                |row| row.get(0),
+            )
+            .map_err(|error| self.sqlite_error(error))
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<String>, Error> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection
+            .prepare(
+                "SELECT key FROM blobs WHERE substr(key, 1, length(?1)) = ?1
+                 UNION SELECT key FROM documents WHERE substr(key, 1, length(?1)) = ?1
+                 UNION SELECT key FROM pages WHERE substr(key, 1, length(?1)) = ?1
+                 ORDER BY key",
+            )
+            .map_err(|error| self.sqlite_error(error))?;
+        let keys = statement
+            .query_map(params![prefix], |row| row.get(0))
+            .and_then(|rows| rows.collect::<Result<Vec<String>, _>>())
+            .map_err(|error| self.sqlite_error(error))?;
+        Ok(keys)
+    }
+
+    fn put_page(
+        &self,
+        key: &str,
+        fetch: &FetchInfo,
+        compressed_html: &[u8],
+        article: &Article,
+    ) -> Result<(), Error> {
+        let section = article
+            .section
+            .clone()
+            .or_else(|| key.split_once('/').map(|(section, _)| section.to_string()));
+        let published = article.published_date.as_ref().map(ParsedDate::iso);
+
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection
+            .transaction()
+            .map_err(|error| self.sqlite_error(error))?;
+        let result = (|| {
+            let fetch_id: Option<i64> = transaction
+                .query_row(
+                    "SELECT id FROM fetches WHERE url = ?1 ORDER BY id DESC LIMIT 1",
+                    params![fetch.url],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            transaction.execute(
+                "INSERT OR REPLACE INTO pages (key, url, fetch_id, html_gz) VALUES (?1, ?2, ?3, ?4)",
+                params![key, fetch.url, fetch_id, compressed_html],
+            )?;
+            transaction.execute(
+                "INSERT OR REPLACE INTO articles
+                    (page_key, url, title, section, published, published_raw, lead, text)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                params![
+                    key,
+                    fetch.url,
+                    article.title,
+                    section,
+                    published,
+                    article.published,
+                    article.lead,
+                    article.text,
+                ],
+            )?;
+            transaction.execute("DELETE FROM articles_fts WHERE page_key = ?1", params![key])?;
+            transaction.execute(
+                "INSERT INTO articles_fts (page_key, title, lead, text) VALUES (?1, ?2, ?3, ?4)",
+                params![key, article.title, article.lead, article.text],
+            )?;
+            Ok(())
+        })();
+        result.map_err(|error| self.sqlite_error(error))?;
+        transaction
+            .commit()
+            .map_err(|error| self.sqlite_error(error))
+    }
+
+    fn record_fetch(&self, fetch: &FetchInfo) -> Result<(), Error> {
+        self.connection
+            .lock()
+            .unwrap()
+            .execute(
+                "INSERT INTO fetches (url, final_url, status, content_type, fetched_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    fetch.url,
+                    fetch.final_url,
+                    fetch.status,
+                    fetch.content_type,
+                    fetch.fetched_at.to_rfc3339(),
+                ],
+            )
+            .map_err(|error| self.sqlite_error(error))?;
+        Ok(())
+    }
+}
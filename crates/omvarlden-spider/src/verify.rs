@@ -0,0 +1,278 @@
+//! Checking the integrity of an output directory.
+//!
+//! Every file is checked by type: `.html.gz` must decompress, `.json` and each
+//! line of `.jsonl` must parse and blobs in `cas/blobs` must match their hash.
+//! Files recorded in the crawl manifest must exist and match the size and
+//! SHA-256 recorded when they were stored, while files the manifest doesn't
+//! know about are reported as orphaned, as are temporary files left by an
+//! interrupted write.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    io::Write,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::archive::ARCHIVE_FILE;
+use crate::cas::CAS_DIR;
+use crate::failures::FAILURES_FILE;
+use crate::files::{files_with_suffix, read_gzip, read_json, read_lines, sha256_file, AtomicFile};
+use crate::frontier::FRONTIER_FILE;
+use crate::manifest::{read_manifest, MANIFEST_FILE};
+use crate::stats::STATS_FILE;
+use crate::storage::{is_zip_archive, TAR_FILE};
+use crate::Error;
+
+/// Files in the output directory that are not crawl outputs.
+const AUXILIARY_FILES: [&str; 11] = [
+    MANIFEST_FILE,
+    ARCHIVE_FILE,
+    FAILURES_FILE,
+    FRONTIER_FILE,
+    STATS_FILE,
+    "episodes.jsonl",
+    "episodes.csv",
+    "omvarlden.sqlite",
+    "omvarlden.sqlite-wal",
+    "omvarlden.sqlite-shm",
+    TAR_FILE,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ProblemKind {
+    /// Recorded in the manifest, but not on disk.
+    Missing,
+    /// Unreadable, or different from what the manifest recorded.
+    Corrupt,
+    /// On disk, but not recorded in the manifest.
+    Orphaned,
+}
+
+impl ProblemKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Missing => "missing",
+            Self::Corrupt => "corrupt",
+            Self::Orphaned => "orphaned",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    pub kind: ProblemKind,
+    /// Relative to the output directory.
+    pub path: PathBuf,
+    /// The URL the file was fetched from, if known.
+    pub url: Option<String>,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerifyReport {
+    pub files_checked: usize,
+    pub problems: Vec<Problem>,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    pub fn count(&self, kind: ProblemKind) -> usize {
+        self.problems
+            .iter()
+            .filter(|problem| problem.kind == kind)
+            .count()
+    }
+
+    /// The URLs to fetch again to repair missing and corrupt files.
+    pub fn requeue_urls(&self) -> Vec<String> {
+        self.problems
+            .iter()
+            .filter(|problem| problem.kind != ProblemKind::Orphaned)
+            .filter_map(|problem| problem.url.clone())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect()
+    }
+}
+
+/// What the manifest last recorded for a stored file.
+struct Recorded {
+    url: String,
+    size: Option<u64>,
+    sha256: Option<String>,
+}
+
+pub fn verify(output_path: &Path) -> Result<VerifyReport, Error> {
+    let recorded = recorded_outputs(output_path)?;
+    let mut report = VerifyReport::default();
+    for path in files_with_suffix(output_path, "") {
+        let relative = path.strip_prefix(output_path).unwrap_or(&path);
+        let name = relative.to_string_lossy();
+        report.files_checked += 1;
+        let record = recorded.get(relative);
+        let url = record.map(|record| record.url.as_str());
+
+        if name.ends_with(".tmp") {
+            report.problems.push(problem(
+                ProblemKind::Orphaned,
+                relative,
+                None,
+                "temporary file left by an interrupted write".to_string(),
+            ));
+            continue;
+        }
+        if let Err(reason) = check_readable(&path, relative, &name) {
+            report
+                .problems
+                .push(problem(ProblemKind::Corrupt, relative, url, reason));
+            continue;
+        }
+        match record {
+            Some(record) => {
+                if let Some(reason) = check_recorded(&path, record)? {
+                    report
+                        .problems
+                        .push(problem(ProblemKind::Corrupt, relative, url, reason));
+                }
+            }
+            None if !is_auxiliary(relative, &name) => {
+                report.problems.push(problem(
+                    ProblemKind::Orphaned,
+                    relative,
+                    None,
+                    "not in the crawl manifest".to_string(),
+                ));
+            }
+            None => {}
+        }
+    }
+
+    for (relative, record) in &recorded {
+        if !output_path.join(relative).is_file() {
+            report.problems.push(problem(
+                ProblemKind::Missing,
+                relative,
+                Some(&record.url),
+                "recorded in the crawl manifest".to_string(),
+            ));
+        }
+    }
+    report
+        .problems
+        .sort_by(|a, b| (a.kind, &a.path).cmp(&(b.kind, &b.path)));
+    Ok(report)
+}
+
+fn problem(kind: ProblemKind, path: &Path, url: Option<&str>, reason: String) -> Problem {
+    tracing::warn!("{} '{}': {}", kind.as_str(), path.display(), reason);
+    Problem {
+        kind,
+        path: path.to_path_buf(),
+        url: url.map(str::to_string),
+        reason,
+    }
+}
+
+/// The files recorded in the crawl manifest, by path relative to `output_path`.
+fn recorded_outputs(output_path: &Path) -> Result<HashMap<PathBuf, Recorded>, Error> {
+    let manifest_path = output_path.join(MANIFEST_FILE);
+    let mut recorded = HashMap::new();
+    if !manifest_path.is_file() {
+        tracing::warn!("no crawl manifest in '{}'", output_path.display());
+        return Ok(recorded);
+    }
+    // later entries replace earlier ones, the file may have been fetched again
+    for entry in read_manifest(&manifest_path)? {
+        let url = entry["url"].as_str().unwrap_or_default();
+        for output in entry["outputs"].as_array().into_iter().flatten() {
+            let Some(path) = output["path"].as_str() else {
+                continue;
+            };
+            recorded.insert(
+                PathBuf::from(path),
+                Recorded {
+                    url: url.to_string(),
+                    size: output["size"].as_u64(),
+                    sha256: output["sha256"].as_str().map(str::to_string),
+                },
+            );
+        }
+    }
+    Ok(recorded)
+}
+
+/// Check that the file can be read as what its name says it is.
+fn check_readable(path: &Path, relative: &Path, name: &str) -> Result<(), String> {
+    if name.ends_with(".gz") {
+        read_gzip(path).map_err(|error| error.to_string())?;
+    } else if name.ends_with(".jsonl") {
+        for (number, line) in read_lines(path)
+            .map_err(|error| error.to_string())?
+            .iter()
+            .enumerate()
+        {
+            serde_json::from_str::<serde_json::Value>(line)
+                .map_err(|error| format!("line {}: {}", number + 1, error))?;
+        }
+    } else if name.ends_with(".json") {
+        read_json(path).map_err(|error| error.to_string())?;
+    } else if let Some(hash) = blob_hash(relative) {
+        let (sha256, _) = sha256_file(path).map_err(|error| error.to_string())?;
+        if sha256 != hash {
+            return Err(format!("content has SHA-256 {}", sha256));
+        }
+    }
+    Ok(())
+}
+
+/// Compare the file with the size and checksum recorded in the manifest.
+fn check_recorded(path: &Path, record: &Recorded) -> Result<Option<String>, Error> {
+    if record.size.is_none() && record.sha256.is_none() {
+        return Ok(None);
+    }
+    let (sha256, size) = sha256_file(path)?;
+    if let Some(expected) = record.size.filter(|expected| *expected != size) {
+        return Ok(Some(format!("size {}, expected {}", size, expected)));
+    }
+    if let Some(expected) = record
+        .sha256
+        .as_ref()
+        .filter(|expected| **expected != sha256)
+    {
+        return Ok(Some(format!("SHA-256 {}, expected {}", sha256, expected)));
+    }
+    Ok(None)
+}
+
+/// The hash a content-addressed blob is named after.
+fn blob_hash(relative: &Path) -> Option<&str> {
+    if !relative.starts_with(Path::new(CAS_DIR).join("blobs")) {
+        return None;
+    }
+    let name = relative.file_name()?.to_str()?;
+    (name.len() == 64 && name.bytes().all(|b| b.is_ascii_hexdigit())).then_some(name)
+}
+
+fn is_auxiliary(relative: &Path, name: &str) -> bool {
+    AUXILIARY_FILES
+        .iter()
+        .any(|file| relative == Path::new(file))
+        || relative.components().next() == Some(Component::Normal(CAS_DIR.as_ref()))
+        || (relative == Path::new(name) && is_zip_archive(name))
+        || name.ends_with(".meta.json")
+}
+
+/// Write `urls` to `path`, one per line.
+pub fn write_requeue(path: &Path, urls: &[String]) -> Result<(), Error> {
+    let mut file = AtomicFile::create(path)?;
+    for url in urls {
+        writeln!(file, "{}", url).map_err(|error| Error::IoWrite {
+            path: path.to_path_buf(),
+            source: error,
+        })?;
+    }
+    file.commit()
+}